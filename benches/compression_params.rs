@@ -0,0 +1,70 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+use std::iter;
+
+use criterion::measurement::WallTime;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use rand::random;
+use refpack::data::compression::{CompressionOptions, CompressionParams};
+use refpack::easy_compress;
+use refpack::format::Reference;
+
+const LENGTH: usize = 1 << 18;
+
+/// (name, options) of the presets, each next to the `Custom` options that
+/// produce the same output with the parameters looked up at runtime
+const OPTIONS: [(&str, CompressionOptions); 4] = [
+    ("Fast", CompressionOptions::Fast),
+    (
+        "Custom FAST",
+        CompressionOptions::Custom(CompressionParams::FAST),
+    ),
+    ("Fastest", CompressionOptions::Fastest),
+    (
+        "Custom FASTEST",
+        CompressionOptions::Custom(CompressionParams::FASTEST),
+    ),
+];
+
+fn low_entropy_vec(len: usize) -> Vec<u8> {
+    iter::repeat_with(|| random::<u8>() % 4).take(len).collect()
+}
+
+fn repeating_text_vec(len: usize) -> Vec<u8> {
+    b"the quick brown fox jumps over the lazy dog "
+        .iter()
+        .copied()
+        .cycle()
+        .take(len)
+        .collect()
+}
+
+fn compression_params_bench(c: &mut Criterion<WallTime>) {
+    let mut group = c.benchmark_group("Compression params");
+    group.throughput(Throughput::Bytes(LENGTH as u64));
+
+    for (input_name, input) in [
+        ("low entropy", low_entropy_vec(LENGTH)),
+        ("repeating text", repeating_text_vec(LENGTH)),
+    ] {
+        for (name, options) in OPTIONS {
+            group.bench_with_input(BenchmarkId::new(name, input_name), &input, |b, i| {
+                b.iter(|| easy_compress::<Reference>(i, options))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default();
+    targets = compression_params_bench
+);
+criterion_main!(benches);
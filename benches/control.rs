@@ -40,6 +40,7 @@ fn repeating_short_control_data<F: Format>(repeats: usize) -> Vec<u8> {
     let header = Header {
         compressed_length: Some(compression_length as u32),
         decompressed_length: (repeats + 1) as u32,
+        ..Header::default()
     };
 
     writer.set_position(0);
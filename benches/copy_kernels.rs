@@ -0,0 +1,96 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+use std::hint::black_box;
+use std::io::Cursor;
+
+use criterion::measurement::WallTime;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use refpack::data::control::{Command, Control};
+use refpack::format::{Format, Reference};
+use refpack::header::Header;
+use refpack::header::mode::Mode as HeaderMode;
+use refpack::{decompress, easy_decompress};
+
+const LITERAL_LENGTH: usize = 16;
+
+const COPY_COUNT: usize = 256;
+
+/// (name, offset, length) of the copies to benchmark
+const COPIES: [(&str, u32, u16); 8] = [
+    ("offset 1", 1, 1028),
+    ("offset 2 short", 2, 10),
+    ("offset 4 short", 4, 67),
+    ("offset 2", 2, 1028),
+    ("offset 3", 3, 1028),
+    ("offset 4", 4, 1028),
+    ("offset 12", 12, 1028),
+    ("non-overlapping", 16, 16),
+];
+
+/// Data consisting of a literal block followed by `COPY_COUNT` copies with
+/// the same offset and length
+fn repeating_copy_data<F: Format>(offset: u32, length: u16) -> (Vec<u8>, usize) {
+    let literals: Vec<u8> = (0..LITERAL_LENGTH as u8).collect();
+    let mut controls = vec![Control::new_literal_block(&literals)];
+    controls.append(&mut vec![
+        Control::new(
+            Command::new(offset, length, 0),
+            vec![]
+        );
+        COPY_COUNT
+    ]);
+    controls.push(Control::new_stop(&[]));
+
+    let decompressed_length = LITERAL_LENGTH + COPY_COUNT * length as usize;
+
+    let mut writer = Cursor::new(vec![]);
+    let header_length = F::HeaderMode::length(decompressed_length);
+    writer.set_position(header_length as u64);
+
+    for control in controls {
+        control.write(&mut writer).unwrap();
+    }
+
+    let header = Header {
+        compressed_length: Some((writer.position() as usize - header_length) as u32),
+        decompressed_length: decompressed_length as u32,
+        ..Header::default()
+    };
+    writer.set_position(0);
+    header.write::<F::HeaderMode>(&mut writer).unwrap();
+
+    (writer.into_inner(), decompressed_length)
+}
+
+fn copy_kernel_bench(c: &mut Criterion<WallTime>) {
+    let mut group = c.benchmark_group("Copy kernels");
+
+    for (name, offset, length) in COPIES {
+        let (input, decompressed_length) = repeating_copy_data::<Reference>(offset, length);
+        group.throughput(Throughput::Bytes(decompressed_length as u64));
+
+        group.bench_with_input(BenchmarkId::new("easy_decompress", name), &input, |b, i| {
+            b.iter(|| easy_decompress::<Reference>(i))
+        });
+        group.bench_with_input(BenchmarkId::new("decompress", name), &input, |b, i| {
+            b.iter(|| {
+                let mut out_buf = Cursor::new(Vec::with_capacity(decompressed_length));
+                decompress::<Reference>(black_box(&mut i.as_slice()), black_box(&mut out_buf))
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default();
+    targets = copy_kernel_bench
+);
+criterion_main!(benches);
@@ -54,6 +54,7 @@ fn bench_set(group: &mut BenchmarkGroup<WallTime>, input_vec: &[u8]) {
     for compression_options in [
         CompressionOptions::Fastest,
         CompressionOptions::Fast,
+        CompressionOptions::Lazy,
         CompressionOptions::Optimal,
     ] {
         group.bench_with_input(
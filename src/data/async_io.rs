@@ -0,0 +1,309 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Decompression and compression through [AsyncRead] and [AsyncWrite]
+//!
+//! Only the traits from `futures-io` are used, so these work with any runtime
+//! that provides them or has a compatibility layer for them. Decompression
+//! is driven by [Decoder], which parses the header and controls the same way
+//! as the synchronous functions while never blocking on the reader.
+
+use std::future::poll_fn;
+use std::io;
+use std::io::ErrorKind;
+use std::pin::Pin;
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::data::compression::CompressionOptions;
+use crate::data::decompression::{Decoder, DecompressionReport};
+use crate::format::Format;
+use crate::{RefPackError, RefPackResult, easy_compress};
+
+/// Amount of compressed data read from the reader at a time
+const CHUNK_SIZE: usize = 1 << 13;
+
+async fn read(reader: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, buf)).await {
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+async fn read_exact(reader: &mut (impl AsyncRead + Unpin), mut buf: &mut [u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        let read = read(reader, buf).await?;
+        if read == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        buf = &mut buf[read..];
+    }
+    Ok(())
+}
+
+async fn write_all(writer: &mut (impl AsyncWrite + Unpin), mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, buf)).await {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(written) => buf = &buf[written..],
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+async fn flush(writer: &mut (impl AsyncWrite + Unpin)) -> io::Result<()> {
+    poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx)).await
+}
+
+/// Decompress `refpack` data from an [AsyncRead] into an [AsyncWrite].
+///
+/// Output is written as it is decompressed, and only the last
+/// [LONG_OFFSET_MAX] bytes of it are kept in memory, so the decompressed length
+/// in the header doesn't affect memory usage.
+///
+/// The reader is read in chunks, so it may have been read past the end of the
+/// data afterwards. The returned report contains the exact amount of bytes
+/// that belonged to the data.
+///
+/// [LONG_OFFSET_MAX]: crate::data::control::LONG_OFFSET_MAX
+///
+/// # Example
+///
+/// ```
+/// # futures::executor::block_on(async {
+/// use futures::io::Cursor;
+/// use refpack::format::Reference;
+/// use refpack::{CompressionOptions, decompress_async, easy_compress};
+///
+/// let compressed = easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+///
+/// let mut output = Cursor::new(vec![]);
+/// decompress_async::<Reference>(&mut compressed.as_slice(), &mut output)
+///     .await
+///     .unwrap();
+///
+/// assert_eq!(output.into_inner(), b"Hello World!");
+/// # });
+/// ```
+///
+/// # Returns
+/// a [DecompressionReport] with the amount of bytes read and written
+///
+/// # Errors
+/// - [RefPackError::BadMagic]: Header magic was malformed
+/// - [RefPackError::BadFlags]: Header flags were malformed
+/// - [RefPackError::ControlError]: Invalid control code operation was attempted
+///   to be performed. This normally indicated corrupted or invalid refpack
+///   data
+/// - [RefPackError::Io]: Generic IO error occured while attempting to read or
+///   write data, including the reader ending before the stopcode
+pub async fn decompress_async<F: Format>(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> RefPackResult<DecompressionReport> {
+    let mut decoder = Decoder::new::<F>();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut consumed = 0;
+
+    loop {
+        let read = read(reader, &mut chunk).await?;
+        if read == 0 {
+            return Err(RefPackError::Io(ErrorKind::UnexpectedEof.into()));
+        }
+
+        let progress = decoder.feed(&chunk[..read])?;
+        consumed += progress.consumed;
+
+        write_all(writer, decoder.output()).await?;
+        decoder.consume_output(decoder.output().len());
+
+        // the header is always known by the time the stopcode is reached
+        if let Some(header) = decoder.header().filter(|_| progress.finished) {
+            flush(writer).await?;
+            return Ok(DecompressionReport {
+                header,
+                consumed,
+                produced: decoder.position(),
+            });
+        }
+    }
+}
+
+/// Compress `length` bytes from an [AsyncRead] into an [AsyncWrite].
+/// Equivalent to [compress](crate::compress).
+///
+/// The input is read asynchronously, but compressing it is not. For large
+/// inputs, consider compressing on a thread where blocking is allowed instead.
+///
+/// # Example
+///
+/// ```
+/// # futures::executor::block_on(async {
+/// use futures::io::Cursor;
+/// use refpack::format::Reference;
+/// use refpack::{CompressionOptions, compress_async, easy_decompress};
+///
+/// let input = b"Hello World!";
+///
+/// let mut output = Cursor::new(vec![]);
+/// compress_async::<Reference>(
+///     input.len(),
+///     &mut input.as_slice(),
+///     &mut output,
+///     CompressionOptions::Fast,
+/// )
+/// .await
+/// .unwrap();
+///
+/// assert_eq!(
+///     easy_decompress::<Reference>(output.get_ref()).unwrap(),
+///     input
+/// );
+/// # });
+/// ```
+///
+/// # Errors
+/// - [RefPackError::EmptyInput]: Length provided is 0
+/// - [RefPackError::InputTooLarge]: Input or compressed data is too large for
+///   the header format
+/// - [RefPackError::Io]: Generic IO error when reading or writing
+pub async fn compress_async<F: Format>(
+    length: usize,
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    compression_options: CompressionOptions,
+) -> RefPackResult<()> {
+    let mut buf = vec![0; length];
+    read_exact(reader, &mut buf).await?;
+    let out = easy_compress::<F>(&buf, compression_options)?;
+    write_all(writer, &out).await?;
+    flush(writer).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::task::{Context, Poll};
+
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::easy_decompress;
+    use crate::format::{Reference, SimEA};
+
+    /// Reader that returns at most `step` bytes at a time, and is pending
+    /// before every read
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+        pending: bool,
+    }
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let amount = buf.len().min(self.step).min(self.data.len());
+            buf[..amount].copy_from_slice(&self.data[..amount]);
+            self.data = &self.data[amount..];
+            Poll::Ready(Ok(amount))
+        }
+    }
+
+    #[proptest]
+    fn symmetrical_async(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let mut compressed = Cursor::new(vec![]);
+        block_on(compress_async::<SimEA>(
+            input.len(),
+            &mut input.as_slice(),
+            &mut compressed,
+            compression_options,
+        ))?;
+        let compressed = compressed.into_inner();
+
+        let mut got = Cursor::new(vec![]);
+        let report = block_on(decompress_async::<SimEA>(
+            &mut compressed.as_slice(),
+            &mut got,
+        ))?;
+
+        prop_assert_eq!(report.consumed, compressed.len());
+        prop_assert_eq!(report.produced, input.len());
+        prop_assert_eq!(got.into_inner(), input);
+    }
+
+    #[proptest]
+    fn decompresses_trickled_input(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        #[strategy(1..=16usize)] step: usize,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, CompressionOptions::Fast)?;
+        let mut reader = Trickle {
+            data: &compressed,
+            step,
+            pending: false,
+        };
+
+        let mut got = Cursor::new(vec![]);
+        block_on(decompress_async::<Reference>(&mut reader, &mut got))?;
+
+        prop_assert_eq!(got.into_inner(), easy_decompress::<Reference>(&compressed)?);
+    }
+
+    #[test]
+    fn reports_consumed_before_trailing_data() {
+        let mut compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let length = compressed.len();
+        compressed.extend_from_slice(b"trailing");
+
+        let mut got = Cursor::new(vec![]);
+        let report = block_on(decompress_async::<Reference>(
+            &mut compressed.as_slice(),
+            &mut got,
+        ))
+        .unwrap();
+
+        assert_eq!(report.consumed, length);
+        assert_eq!(got.into_inner(), b"Hello World!");
+    }
+
+    #[test]
+    fn errors_on_truncated_input() {
+        let compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let truncated = &compressed[..compressed.len() - 1];
+
+        let error = block_on(decompress_async::<Reference>(
+            &mut &truncated[..],
+            &mut Cursor::new(vec![]),
+        ))
+        .unwrap_err();
+
+        assert!(
+            matches!(error, RefPackError::Io(error) if error.kind() == ErrorKind::UnexpectedEof)
+        );
+    }
+}
@@ -0,0 +1,333 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Compression and decompression of many independent buffers at once
+//!
+//! Archives such as DBPF packages contain thousands of separately compressed
+//! entries. Rather than spawning a thread per entry, a fixed amount of worker
+//! threads take entries one at a time until all are done, each keeping its
+//! allocations around from one entry to the next.
+
+use std::num::NonZeroUsize;
+use std::panic::resume_unwind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{mem, thread};
+
+use crate::RefPackResult;
+use crate::data::compression::{CompressionOptions, CompressionScratch, compress_with_scratch};
+use crate::data::decompression::DecompressionOptions;
+use crate::data::decompression::slice::decompress_slice_append;
+use crate::format::Format;
+
+/// The amount of threads to use when `threads` were requested, where `0`
+/// means as many as are available
+pub(crate) fn thread_count(threads: usize) -> usize {
+    if threads == 0 {
+        thread::available_parallelism().map_or(1, NonZeroUsize::get)
+    } else {
+        threads
+    }
+}
+
+/// Run `work` for every index below `count` on up to `threads` threads,
+/// giving every thread its own scratch state
+pub(crate) fn run_batch<S: Default, T: Send>(
+    count: usize,
+    threads: usize,
+    work: impl Fn(&mut S, usize) -> T + Sync,
+) -> Vec<T> {
+    let threads = thread_count(threads).min(count);
+
+    if threads <= 1 {
+        let mut scratch = S::default();
+        return (0..count).map(|index| work(&mut scratch, index)).collect();
+    }
+
+    // entries can differ wildly in size, so threads take the next entry when
+    // they're done instead of being handed a fixed share up front
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<T>> = (0..count).map(|_| None).collect();
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut scratch = S::default();
+                    let mut done = vec![];
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= count {
+                            break done;
+                        }
+                        done.push((index, work(&mut scratch, index)));
+                    }
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            let done = worker.join().unwrap_or_else(|panic| resume_unwind(panic));
+            for (index, result) in done {
+                results[index] = Some(result);
+            }
+        }
+    });
+
+    results.into_iter().flatten().collect()
+}
+
+/// Compress every buffer in `inputs` on up to `threads` threads. Equivalent
+/// to calling [easy_compress](crate::easy_compress) on each of them.
+///
+/// A `threads` of `0` uses as many threads as
+/// [available_parallelism](std::thread::available_parallelism) reports. Each
+/// thread reuses the tables of the compressor between the buffers it
+/// compresses, and compresses them without spawning any threads of its own.
+/// [OptimalSegmented](CompressionOptions::OptimalSegmented) therefore doesn't
+/// split entries, making them identical to
+/// [Optimal](CompressionOptions::Optimal).
+///
+/// With the optimal algorithms, keeping the tables around means peak memory use
+/// per thread is 4 bytes per input byte higher than with
+/// [easy_compress](crate::easy_compress), which frees part of them before
+/// writing the output.
+///
+/// # Returns
+/// the result of compressing each buffer, in the same order as `inputs`. An
+/// error compressing one buffer doesn't affect any of the others.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::Maxis;
+/// use refpack::{CompressionOptions, compress_batch, easy_decompress};
+///
+/// let inputs: [&[u8]; 3] = [b"Hello World!", b"", b"Lorem ipsum"];
+///
+/// let compressed = compress_batch::<Maxis>(&inputs, CompressionOptions::Fast, 2);
+///
+/// assert_eq!(
+///     easy_decompress::<Maxis>(compressed[0].as_ref().unwrap()).unwrap(),
+///     b"Hello World!"
+/// );
+/// assert!(compressed[1].is_err());
+/// ```
+#[must_use]
+pub fn compress_batch<F: Format>(
+    inputs: &[&[u8]],
+    compression_options: CompressionOptions,
+    threads: usize,
+) -> Vec<RefPackResult<Vec<u8>>> {
+    run_batch(
+        inputs.len(),
+        threads,
+        |scratch: &mut CompressionScratch, index| {
+            // the batch already keeps every thread busy, so entries don't use threads of their own
+            // and the allocations of the encoder are kept for the next entry, including those
+            // that are otherwise freed early to lower peak memory use
+            scratch.keep_cost_state = true;
+            compress_with_scratch::<F>(inputs[index], compression_options, scratch, 1)
+        },
+    )
+}
+
+/// Output buffer that a thread of [decompress_batch] decompresses the next
+/// entry into
+#[derive(Default)]
+struct DecodeScratch {
+    output: Vec<u8>,
+}
+
+/// Decompress every buffer in `inputs` on up to `threads` threads, with the
+/// limits in `options` applied to each buffer individually. Equivalent to
+/// calling [easy_decompress_with_options](crate::easy_decompress_with_options)
+/// on each of them.
+///
+/// A `threads` of `0` uses as many threads as
+/// [available_parallelism](std::thread::available_parallelism) reports. Every
+/// output is decompressed straight into the buffer that is returned, which is
+/// allocated up front from the decompressed length in the header as far as
+/// `options` allow. Each thread keeps the buffer of an entry that failed to
+/// decompress for the next one, and when
+/// [trust_header_length](DecompressionOptions::trust_header_length) is off,
+/// starts the next entry with a buffer as large as the last output rather than
+/// growing it from nothing.
+///
+/// # Returns
+/// the result of decompressing each buffer, in the same order as `inputs`. A
+/// corrupt buffer doesn't affect any of the others.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::Maxis;
+/// use refpack::{CompressionOptions, DecompressionOptions, decompress_batch, easy_compress};
+///
+/// let compressed = easy_compress::<Maxis>(b"Hello World!", CompressionOptions::Fast).unwrap();
+/// let inputs: [&[u8]; 2] = [&compressed, b"corrupt"];
+///
+/// let decompressed = decompress_batch::<Maxis>(&inputs, DecompressionOptions::default(), 2);
+///
+/// assert_eq!(decompressed[0].as_ref().unwrap(), b"Hello World!");
+/// assert!(decompressed[1].is_err());
+/// ```
+#[must_use]
+pub fn decompress_batch<F: Format>(
+    inputs: &[&[u8]],
+    options: DecompressionOptions,
+    threads: usize,
+) -> Vec<RefPackResult<Vec<u8>>> {
+    run_batch(
+        inputs.len(),
+        threads,
+        |scratch: &mut DecodeScratch, index| {
+            // on error the buffer is left empty, but keeps its allocation for the next entry
+            decompress_slice_append::<F>(inputs[index], &mut scratch.output, options)?;
+            // a trusted header sizes the buffer of the next entry exactly, anything more would be
+            // handed out with its output
+            let capacity = if options.trust_header_length {
+                0
+            } else {
+                scratch.output.len()
+            };
+            Ok(mem::replace(
+                &mut scratch.output,
+                Vec::with_capacity(capacity),
+            ))
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::format::{Maxis, Reference};
+    use crate::{RefPackError, easy_compress, easy_decompress};
+
+    #[proptest]
+    fn matches_single_compression(
+        #[strategy(proptest::collection::vec(proptest::collection::vec(0..=3u8, 0..=1_000), 0..=16))]
+        inputs: Vec<Vec<u8>>,
+        compression_options: CompressionOptions,
+        #[strategy(0..=4usize)] threads: usize,
+    ) {
+        let inputs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+
+        let compressed = compress_batch::<Maxis>(&inputs, compression_options, threads);
+        prop_assert_eq!(compressed.len(), inputs.len());
+
+        let compressed: Vec<&[u8]> = compressed
+            .iter()
+            .zip(&inputs)
+            .map(|(result, input)| {
+                match result {
+                    Ok(compressed) => {
+                        assert_eq!(
+                            compressed,
+                            &easy_compress::<Maxis>(input, compression_options).unwrap()
+                        );
+                        compressed.as_slice()
+                    }
+                    Err(RefPackError::EmptyInput) => {
+                        assert!(input.is_empty());
+                        // not valid compressed data either
+                        input
+                    }
+                    Err(error) => panic!("unexpected error {error:?}"),
+                }
+            })
+            .collect();
+
+        let decompressed =
+            decompress_batch::<Maxis>(&compressed, DecompressionOptions::default(), threads);
+        for (result, input) in decompressed.iter().zip(&inputs) {
+            match result {
+                Ok(decompressed) => prop_assert_eq!(decompressed, input),
+                Err(_) => prop_assert!(input.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_errors_per_entry() {
+        let compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let mut corrupt = compressed.clone();
+        // a copy reaching back before the start of the output
+        corrupt[4] = 0x7F;
+        let inputs: Vec<&[u8]> = vec![&compressed, &corrupt, &compressed[..6], &compressed];
+
+        let decompressed =
+            decompress_batch::<Reference>(&inputs, DecompressionOptions::default(), 3);
+
+        assert_eq!(decompressed.len(), 4);
+        assert_eq!(decompressed[0].as_ref().unwrap(), b"Hello World!");
+        assert!(matches!(
+            decompressed[1],
+            Err(RefPackError::ControlError { .. })
+        ));
+        assert!(matches!(decompressed[2], Err(RefPackError::Io(_))));
+        assert_eq!(
+            decompressed[3].as_ref().unwrap(),
+            &easy_decompress::<Reference>(&compressed).unwrap()
+        );
+    }
+
+    #[test]
+    fn reuses_tables_across_sizes() {
+        // on one thread, so the tables of each input are reused by the next,
+        // switching between small and large prefix tables
+        let large: Vec<u8> = (0..20_000u32)
+            .map(|i| ((i % 251) ^ (i / 97)) as u8)
+            .collect();
+        let other: Vec<u8> = (0..20_000u32).map(|i| ((i % 13) * i / 7) as u8).collect();
+        let inputs: Vec<&[u8]> = vec![&large, &large[..100], &other, &large[..9_000]];
+
+        let compressed = compress_batch::<Reference>(&inputs, CompressionOptions::Optimal, 1);
+
+        for (result, input) in compressed.iter().zip(&inputs) {
+            assert_eq!(
+                result.as_ref().unwrap(),
+                &easy_compress::<Reference>(input, CompressionOptions::Optimal).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn reuses_output_buffers() {
+        // on one thread, so every entry is decompressed into the buffer left by the one before,
+        // including after a corrupt entry
+        let large: Vec<u8> = (0..20_000u32).map(|i| ((i % 13) * i / 7) as u8).collect();
+        let large = easy_compress::<Reference>(&large, CompressionOptions::Fast).unwrap();
+        let small = easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let inputs: Vec<&[u8]> = vec![&large, &small, &large[..100], &small, &large];
+
+        for trust_header_length in [true, false] {
+            let options = DecompressionOptions {
+                trust_header_length,
+                ..DecompressionOptions::default()
+            };
+            let decompressed = decompress_batch::<Reference>(&inputs, options, 1);
+
+            assert!(decompressed[2].is_err());
+            for (result, input) in decompressed.iter().zip(&inputs) {
+                if let Ok(decompressed) = result {
+                    assert_eq!(decompressed, &easy_decompress::<Reference>(input).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn handles_no_inputs() {
+        assert!(compress_batch::<Reference>(&[], CompressionOptions::Fast, 0).is_empty());
+        assert!(decompress_batch::<Reference>(&[], DecompressionOptions::default(), 0).is_empty());
+    }
+}
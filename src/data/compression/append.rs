@@ -0,0 +1,217 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Appending data to already compressed data
+//!
+//! The controls of the existing data are kept exactly as they are, except for
+//! the stopcode. The literals of the stopcode and the new data are compressed
+//! in its place, with the last [LONG_OFFSET_MAX] bytes of the existing data
+//! available for copies, as if everything had been compressed in one go.
+
+use std::io::Write;
+
+use crate::RefPackResult;
+use crate::data::compression::{
+    CompressionOptions,
+    CompressionScratch,
+    encode_into,
+    write_compressed,
+};
+use crate::data::control::{Command, LONG_OFFSET_MAX};
+use crate::data::decompression::DecompressionOptions;
+use crate::data::decompression::slice::decompress_slice_append;
+use crate::format::Format;
+use crate::header::Header;
+
+/// Find the encoded controls of `input` before the stopcode
+///
+/// # Returns
+/// the encoded controls along with the amount of literals of the stopcode
+fn controls_before_stop<F: Format>(input: &[u8]) -> RefPackResult<(&[u8], usize)> {
+    let mut reader = input;
+    Header::read::<F::HeaderMode>(&mut reader)?;
+    let header_length = input.len() - reader.len();
+
+    let mut position = header_length;
+    loop {
+        let command_position = position;
+        let command = Command::read_slice(input, &mut position)?;
+        position += command.num_of_literal().unwrap_or(0);
+
+        if command.is_stop() {
+            return Ok((
+                &input[header_length..command_position],
+                command.literal as usize,
+            ));
+        }
+    }
+}
+
+/// Append `new_data` to the data compressed in `existing`, without compressing
+/// the existing data again.
+///
+/// The stopcode of `existing` is replaced by the compressed new data, which may
+/// copy from the last 128 KiB of the existing data. Both lengths in the header
+/// are rewritten to cover the combined data, so the result is regular `refpack`
+/// data that decompresses to the existing data followed by `new_data`.
+///
+/// Everything before the stopcode is kept as it is, so appending a lot of small
+/// pieces compresses slightly worse than compressing all data at once. Any data
+/// after the stopcode of `existing` is dropped.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::Reference;
+/// use refpack::{CompressionOptions, append_compress, easy_compress, easy_decompress};
+///
+/// let compressed = easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+/// let appended =
+///     append_compress::<Reference>(&compressed, b" Hello again!", CompressionOptions::Fast)
+///         .unwrap();
+///
+/// assert_eq!(
+///     easy_decompress::<Reference>(&appended).unwrap(),
+///     b"Hello World! Hello again!"
+/// );
+/// ```
+///
+/// # Errors
+/// - [RefPackError::BadMagic]: Header magic of `existing` was malformed
+/// - [RefPackError::BadFlags]: Header flags of `existing` were malformed
+/// - [RefPackError::ControlError]: `existing` contains an invalid control
+/// - [RefPackError::InputTooLarge]: Combined data is too large for the header
+///   format
+/// - [RefPackError::Io]: `existing` ended before its stopcode
+///
+/// [RefPackError::BadMagic]: crate::RefPackError::BadMagic
+/// [RefPackError::BadFlags]: crate::RefPackError::BadFlags
+/// [RefPackError::ControlError]: crate::RefPackError::ControlError
+/// [RefPackError::InputTooLarge]: crate::RefPackError::InputTooLarge
+/// [RefPackError::Io]: crate::RefPackError::Io
+pub fn append_compress<F: Format>(
+    existing: &[u8],
+    new_data: &[u8],
+    compression_options: CompressionOptions,
+) -> RefPackResult<Vec<u8>> {
+    let mut decompressed = vec![];
+    decompress_slice_append::<F>(existing, &mut decompressed, DecompressionOptions::default())?;
+    let (encoded, stop_literals) = controls_before_stop::<F>(existing)?;
+
+    let length = decompressed.len() + new_data.len();
+
+    // the literals of the stopcode are encoded again along with the new data,
+    // only the history before them has to be kept
+    let start = decompressed.len() - stop_literals;
+    let history = start.saturating_sub(LONG_OFFSET_MAX as usize);
+    decompressed.drain(..history);
+    decompressed.extend_from_slice(new_data);
+
+    write_compressed::<F>(length, |writer| {
+        writer.write_all(encoded)?;
+        encode_into(
+            &decompressed,
+            start - history,
+            compression_options,
+            &mut CompressionScratch::default(),
+            0,
+            writer,
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::decompression::validate;
+    use crate::format::{Maxis, Reference, SimEA};
+    use crate::{RefPackError, easy_compress, easy_decompress};
+
+    #[proptest]
+    fn appends_to_existing(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] old: Vec<u8>,
+        #[strategy(proptest::collection::vec(0..=3u8, 0..=1_000))] new: Vec<u8>,
+        old_options: CompressionOptions,
+        new_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<SimEA>(&old, old_options)?;
+        let appended = append_compress::<SimEA>(&compressed, &new, new_options)?;
+
+        let summary = validate::<SimEA>(&appended)?;
+        prop_assert_eq!(summary.compressed_length, appended.len());
+        prop_assert_eq!(easy_decompress::<SimEA>(&appended)?, [old, new].concat());
+    }
+
+    #[proptest]
+    fn appends_repeatedly(
+        #[strategy(proptest::collection::vec(proptest::collection::vec(0..=3u8, 0..=200), 1..=8))]
+        pieces: Vec<Vec<u8>>,
+        options: CompressionOptions,
+    ) {
+        let mut compressed = easy_compress::<Maxis>(b"start", options)?;
+        for piece in &pieces {
+            compressed = append_compress::<Maxis>(&compressed, piece, options)?;
+        }
+
+        let expected = [&b"start"[..], &pieces.concat()].concat();
+        prop_assert_eq!(easy_decompress::<Maxis>(&compressed)?, expected);
+    }
+
+    #[test]
+    fn copies_from_existing() {
+        let old: Vec<u8> = (0..50_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let compressed = easy_compress::<Reference>(&old, CompressionOptions::Optimal).unwrap();
+
+        // the end of the existing data again, which is all within reach
+        let new = &old[old.len() - 10_000..];
+        let appended =
+            append_compress::<Reference>(&compressed, new, CompressionOptions::Optimal).unwrap();
+
+        assert!(appended.len() < compressed.len() + 100);
+        assert_eq!(
+            easy_decompress::<Reference>(&appended).unwrap(),
+            [old.as_slice(), new].concat()
+        );
+    }
+
+    #[test]
+    fn drops_trailing_data() {
+        let mut compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        compressed.extend_from_slice(b"trailing");
+
+        let appended =
+            append_compress::<Reference>(&compressed, b" Bye!", CompressionOptions::Fast).unwrap();
+
+        assert_eq!(
+            validate::<Reference>(&appended).unwrap().compressed_length,
+            appended.len()
+        );
+        assert_eq!(
+            easy_decompress::<Reference>(&appended).unwrap(),
+            b"Hello World! Bye!"
+        );
+    }
+
+    #[test]
+    fn errors_on_truncated_existing() {
+        let compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+
+        let error = append_compress::<Reference>(
+            &compressed[..compressed.len() - 1],
+            b"more",
+            CompressionOptions::Fast,
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, RefPackError::Io(_)));
+    }
+}
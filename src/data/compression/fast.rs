@@ -5,12 +5,12 @@
 //                                                                             /
 ////////////////////////////////////////////////////////////////////////////////
 
-use std::cmp::max;
+use std::cmp::{max, min};
 
-use crate::data::compression::bytes_for_match;
 use crate::data::compression::match_length::match_length;
 use crate::data::compression::prefix_search::hash_chain::HashChain;
 use crate::data::compression::prefix_search::prefix;
+use crate::data::compression::{CompressionParams, bytes_for_match};
 use crate::data::control::{
     COPY_LITERAL_MAX,
     Command,
@@ -23,46 +23,101 @@ use crate::data::control::{
 
 /// The maximum amount of positions in the hash chain that the algorithm will check before stopping the search.
 /// This cannot be a simple configuration variable as doing so will incur some performance penalty in the hot loop.
-const MAX_HASH_CHAIN_SEARCH_ITERATIONS: usize = 0x80;
+/// [encode] is therefore compiled with [FastParams], which hands out the parameters as constants, and
+/// only [Custom](crate::CompressionOptions::Custom) looks them up at runtime.
+/// `benches/compression_params.rs` compares the two.
+pub(crate) const MAX_HASH_CHAIN_SEARCH_ITERATIONS: usize = 0x80;
+
+/// Source of the parameters of the match search of [encode_with]
+pub(crate) trait SearchParams {
+    fn params(&self) -> CompressionParams;
+}
+
+/// [CompressionParams::FAST] as a type, so the search is compiled with its
+/// parameters as constants
+pub(crate) struct FastParams;
+
+impl SearchParams for FastParams {
+    #[inline(always)]
+    fn params(&self) -> CompressionParams {
+        CompressionParams::FAST
+    }
+}
+
+impl SearchParams for CompressionParams {
+    #[inline(always)]
+    fn params(&self) -> CompressionParams {
+        *self
+    }
+}
 
 /// Reads from an incoming `Read` reader and compresses and encodes to
 /// `Vec<Control>`
-pub(crate) fn encode(input: &[u8]) -> Vec<Control> {
+///
+/// Only the bytes from `start` onward are encoded, the bytes before it are only
+/// used as matches for copies.
+pub(crate) fn encode(input: &[u8], start: usize) -> Vec<Control> {
+    encode_with(input, start, &FastParams)
+}
+
+/// [encode], with the effort spent searching for matches given by `params`
+pub(crate) fn encode_with(input: &[u8], start: usize, params: &impl SearchParams) -> Vec<Control> {
+    let params = params.params();
     let mut controls: Vec<Control> = vec![];
     let mut prefix_table = HashChain::new(input.len());
 
-    let mut i = 0;
     let end = max(3, input.len()) - 3;
+    for k in 0..min(start, end) {
+        let _ = prefix_table.insert(prefix(&input[k..]), k as u32);
+    }
+    let mut i = start;
     let mut literal_block: Vec<u8> = Vec::with_capacity(LITERAL_MAX as usize);
+    let max_offset = min(params.max_offset, LONG_OFFSET_MAX as usize);
+    let min_match_length = max(params.min_match_length, 3);
     while i < end {
         let key = prefix(&input[i..]);
 
         // get the position of the prefix in the table (if it exists)
         let matched = prefix_table.insert(key, i as u32);
 
-        let pair = matched
-            .take(MAX_HASH_CHAIN_SEARCH_ITERATIONS)
-            .filter_map(|matched| {
-                let matched = matched as usize;
-                let distance = i - matched;
-                if distance > LONG_OFFSET_MAX as usize || distance < SHORT_OFFSET_MIN as usize {
-                    None
-                } else {
-                    // find the longest common prefix
-                    let max_copy_len = LONG_LENGTH_MAX as usize;
-                    let match_length = match_length(input, i, matched, max_copy_len, 3);
-
-                    let num_bytes = bytes_for_match(match_length, distance)?.0?;
-                    Some((
-                        matched,
-                        match_length,
-                        match_length as f64 / num_bytes as f64,
-                    ))
-                }
-            })
-            .max_by(|(_, _, r1), (_, _, r2)| r1.total_cmp(r2));
+        // the match with the most bytes copied per byte of command, preferring the furthest
+        // of equally good matches
+        let mut pair = None;
+        let mut best_ratio = 0.0;
+        for matched in matched
+            .take(params.chain_depth)
+            .take_while(|&matched| i - matched as usize <= max_offset)
+        {
+            let matched = matched as usize;
+            let distance = i - matched;
+            if distance < SHORT_OFFSET_MIN as usize {
+                continue;
+            }
+
+            // find the longest common prefix
+            let max_copy_len = LONG_LENGTH_MAX as usize;
+            let match_length = match_length(input, i, matched, max_copy_len, 3);
+            if match_length < min_match_length {
+                continue;
+            }
+
+            let Some(num_bytes) =
+                bytes_for_match(match_length, distance).and_then(|(bytes, _)| bytes)
+            else {
+                continue;
+            };
+            let ratio = match_length as f64 / num_bytes as f64;
+            if ratio >= best_ratio {
+                best_ratio = ratio;
+                pair = Some((matched, match_length));
+            }
+
+            if params.nice_length.is_some_and(|nice| match_length >= nice) {
+                break;
+            }
+        }
 
-        if let Some((found, match_length, _)) = pair {
+        if let Some((found, match_length)) = pair {
             let distance = i - found;
 
             // If the current literal block is longer than the copy limit we need to split the block
@@ -91,11 +146,13 @@ pub(crate) fn encode(input: &[u8]) -> Vec<Control> {
             }
             literal_block.clear();
 
-            for k in (i..).take(match_length).skip(1) {
-                if k >= end {
-                    break;
+            if params.insert_all_positions {
+                for k in (i..).take(match_length).skip(1) {
+                    if k >= end {
+                        break;
+                    }
+                    let _ = prefix_table.insert(prefix(&input[k..]), k as u32);
                 }
-                let _ = prefix_table.insert(prefix(&input[k..]), k as u32);
             }
 
             i += match_length;
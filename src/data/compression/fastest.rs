@@ -5,7 +5,7 @@
 //                                                                             /
 ////////////////////////////////////////////////////////////////////////////////
 
-use std::cmp::max;
+use std::cmp::{max, min};
 
 use crate::data::compression::bytes_for_match;
 use crate::data::compression::match_length::match_length;
@@ -23,12 +23,18 @@ use crate::data::control::{
 
 /// Reads from an incoming `Read` reader and compresses and encodes to
 /// `Vec<Control>`
-pub(crate) fn encode(input: &[u8]) -> Vec<Control> {
+///
+/// Only the bytes from `start` onward are encoded, the bytes before it are only
+/// used as matches for copies.
+pub(crate) fn encode(input: &[u8], start: usize) -> Vec<Control> {
     let mut controls: Vec<Control> = vec![];
     let mut prefix_table = PrefixTable::new(input.len());
 
-    let mut i = 0;
     let end = max(3, input.len()) - 3;
+    for k in 0..min(start, end) {
+        prefix_table.insert(prefix(&input[k..]), k as u32);
+    }
+    let mut i = start;
     let mut literal_block: Vec<u8> = Vec::with_capacity(LITERAL_MAX as usize);
     while i < end {
         let key = prefix(&input[i..]);
@@ -0,0 +1,451 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Greedy matching with lazy evaluation
+//!
+//! Like the fast encoder, this takes the match copying the most bytes per byte
+//! of command at every position. Before committing to it though, a few other
+//! ways to go on are tried as well: writing a literal instead, so the match at
+//! the next position can be taken, or shortening the match, giving the match
+//! after it more room. The latter is where most of the gain over the fast
+//! encoder comes from, as a greedy match often swallows the start of a much
+//! better match.
+//!
+//! Every alternative is followed by the choices the fast encoder would make
+//! from there, until it ends up in the same place as the fast encoder does
+//! without it: at the same position with the same literals waiting to be
+//! written. Only an alternative that has written fewer bytes by then is taken.
+//! From the same place on the fast encoder makes the same choices either way,
+//! so every alternative taken makes the output smaller than that of the fast
+//! encoder, and the output is never larger than that of the fast encoder.
+
+use std::cmp::{max, min};
+
+use crate::data::compression::bytes_for_match;
+use crate::data::compression::fast::MAX_HASH_CHAIN_SEARCH_ITERATIONS;
+use crate::data::compression::match_length::match_length;
+use crate::data::compression::prefix_search::hash_chain::HashChain;
+use crate::data::compression::prefix_search::prefix;
+use crate::data::control::{
+    COPY_LITERAL_MAX,
+    Command,
+    Control,
+    LITERAL_MAX,
+    LONG_LENGTH_MAX,
+    SHORT_OFFSET_MIN,
+};
+
+/// Amount of lengths a match may be shortened by to give the match after it
+/// more room
+const SHORTEN_WINDOW: usize = 2;
+
+/// Amount of choices of the fast encoder that an alternative is followed for
+/// before giving up on it ending up in the same place
+const SYNC_STEPS: usize = 12;
+
+/// Amount of matches kept per position, which gives the cheapest copy
+/// when shortening matches
+const MATCHES_PER_POSITION: usize = 6;
+
+/// Amount of searched positions that are remembered. Positions are searched
+/// again when they are forgotten, so this only needs to cover the positions
+/// that are usually looked ahead at.
+const SEARCHED_POSITIONS: usize = 1 << 10;
+
+#[derive(Copy, Clone)]
+struct Match {
+    distance: usize,
+    length: usize,
+    /// bytes of the command copying the match
+    bytes: usize,
+}
+
+impl Match {
+    fn new(distance: usize, length: usize) -> Option<Self> {
+        let bytes = bytes_for_match(length, distance)?.0?;
+        Some(Self {
+            distance,
+            length,
+            bytes,
+        })
+    }
+
+    /// Whether `self` copies more bytes per byte of command than `other`
+    fn beats(self, other: Self) -> bool {
+        self.length * other.bytes > other.length * self.bytes
+    }
+}
+
+/// Matches found at a single position
+#[derive(Copy, Clone, Default)]
+struct Matches {
+    /// the closest matches of increasing length, as pairs of distance and length
+    found: [(u32, u16); MATCHES_PER_POSITION],
+    count: usize,
+    /// the match the fast encoder would pick: the most bytes copied per byte of command,
+    /// preferring the furthest of equally good matches
+    best: Option<Match>,
+}
+
+impl Matches {
+    /// The cheapest match copying exactly `length` bytes
+    fn with_length(&self, length: usize) -> Option<Match> {
+        self.found[..self.count]
+            .iter()
+            .filter(|&&(_, found_length)| found_length as usize >= length)
+            .filter_map(|&(distance, _)| Match::new(distance as usize, length))
+            .min_by_key(|found| found.bytes)
+    }
+}
+
+/// How far an encoding of the input has come
+#[derive(Copy, Clone)]
+struct State {
+    /// the next position to encode
+    position: usize,
+    /// literals waiting to be written along with the next copy
+    literals: usize,
+    /// bytes written so far, counting the waiting literals
+    bytes: usize,
+}
+
+impl State {
+    /// Encode the next byte as a literal, writing out the literal block once it
+    /// is full
+    fn literal(self) -> Self {
+        let literals = (self.literals + 1) % LITERAL_MAX as usize;
+        Self {
+            position: self.position + 1,
+            literals,
+            bytes: self.bytes + 1 + usize::from(literals == 0),
+        }
+    }
+
+    /// Encode the next bytes as `found`, along with the waiting literals
+    fn copy(self, found: Match) -> Self {
+        Self {
+            position: self.position + found.length,
+            literals: 0,
+            // literals that don't fit into the copy command get a literal block of their own
+            bytes: self.bytes
+                + found.bytes
+                + usize::from(self.literals > COPY_LITERAL_MAX as usize),
+        }
+    }
+
+    /// Bytes written once the remaining input is written as literals along
+    /// with the stopcode
+    fn finished_bytes(self, length: usize) -> usize {
+        let rest = length - self.position;
+        self.bytes + rest + usize::from(self.literals + rest > COPY_LITERAL_MAX as usize) + 1
+    }
+
+    /// Whether the encoder goes on the same way from `self` as from `other`
+    fn meets(self, other: Self) -> bool {
+        self.position == other.position && self.literals == other.literals
+    }
+}
+
+/// The matches at every position of the input
+struct Searcher<'a> {
+    input: &'a [u8],
+    end: usize,
+    prefix_table: HashChain,
+    /// first position that has not been inserted into the prefix table yet
+    inserted: usize,
+    /// matches of searched positions, indexed by position
+    searched: Vec<(usize, Matches)>,
+}
+
+impl<'a> Searcher<'a> {
+    /// A searcher with the positions before `start` already inserted
+    fn new(input: &'a [u8], start: usize) -> Self {
+        let end = max(3, input.len()) - 3;
+        let mut prefix_table = HashChain::new(input.len());
+        for k in 0..min(start, end) {
+            let _ = prefix_table.insert(prefix(&input[k..]), k as u32);
+        }
+
+        Self {
+            input,
+            end,
+            prefix_table,
+            inserted: min(start, end),
+            searched: vec![(usize::MAX, Matches::default()); SEARCHED_POSITIONS],
+        }
+    }
+
+    /// Search the matches at `pos`, inserting every position up to it.
+    ///
+    /// Positions can be searched in any order, as long as they are not too far
+    /// behind the furthest position searched yet for the prefix table to
+    /// still hold them.
+    fn search(&mut self, pos: usize) -> Matches {
+        if pos >= self.end {
+            return Matches::default();
+        }
+        let (searched_pos, matches) = self.searched[pos % SEARCHED_POSITIONS];
+        if searched_pos == pos {
+            return matches;
+        }
+
+        for k in self.inserted..=pos {
+            let _ = self.prefix_table.insert(prefix(&self.input[k..]), k as u32);
+        }
+        self.inserted = max(self.inserted, pos + 1);
+
+        let mut matches = Matches::default();
+        for matched in self
+            .prefix_table
+            .chain(pos as u32)
+            .take(MAX_HASH_CHAIN_SEARCH_ITERATIONS)
+        {
+            let matched = matched as usize;
+            let distance = pos - matched;
+            if distance < SHORT_OFFSET_MIN as usize {
+                continue;
+            }
+
+            let length = match_length(self.input, pos, matched, LONG_LENGTH_MAX as usize, 3);
+            let Some(found) = Match::new(distance, length) else {
+                continue;
+            };
+            if matches.best.is_none_or(|best| !best.beats(found)) {
+                matches.best = Some(found);
+            }
+
+            // further matches need to be longer to be of any use when shortening
+            if matches.count > 0 && length <= matches.found[matches.count - 1].1 as usize {
+                continue;
+            }
+            // the longest match is always kept
+            matches.count = min(matches.count, MATCHES_PER_POSITION - 1);
+            matches.found[matches.count] = (distance as u32, length as u16);
+            matches.count += 1;
+        }
+
+        self.searched[pos % SEARCHED_POSITIONS] = (pos, matches);
+        matches
+    }
+
+    /// The choice the fast encoder makes at `state`
+    fn fast_step(&mut self, state: State) -> State {
+        match self.search(state.position).best {
+            Some(found) => state.copy(found),
+            None => state.literal(),
+        }
+    }
+
+    /// The amount of bytes saved by going on from `alternative` instead of
+    /// `fast`, with the fast encoder making every choice after either of them.
+    /// Only known when the two end up in the same place within [SYNC_STEPS]
+    /// choices, and only returned when some bytes are saved.
+    fn saved(&mut self, mut alternative: State, mut fast: State) -> Option<usize> {
+        for _ in 0..=SYNC_STEPS {
+            if alternative.position >= self.end && fast.position >= self.end {
+                let length = self.input.len();
+                return fast
+                    .finished_bytes(length)
+                    .checked_sub(alternative.finished_bytes(length))
+                    .filter(|&saved| saved > 0);
+            }
+            if alternative.meets(fast) {
+                return fast
+                    .bytes
+                    .checked_sub(alternative.bytes)
+                    .filter(|&saved| saved > 0);
+            }
+
+            // the one that is behind catches up
+            if alternative.position <= fast.position {
+                alternative = self.fast_step(alternative);
+            } else {
+                fast = self.fast_step(fast);
+            }
+        }
+        None
+    }
+}
+
+/// Push a single literal byte, writing out the literal block once it is full
+fn push_literal(controls: &mut Vec<Control>, literal_block: &mut Vec<u8>, byte: u8) {
+    literal_block.push(byte);
+    if literal_block.len() >= LITERAL_MAX as usize {
+        controls.push(Control::new_literal_block(literal_block));
+        literal_block.clear();
+    }
+}
+
+/// Push a copy, along with the literals before it
+fn push_copy(controls: &mut Vec<Control>, literal_block: &mut Vec<u8>, found: Match) {
+    // If the current literal block is longer than the copy limit we need to split the block
+    let split_point = if literal_block.len() > COPY_LITERAL_MAX as usize {
+        literal_block.len() - (literal_block.len() % 4)
+    } else {
+        0
+    };
+    if split_point > 0 {
+        controls.push(Control::new_literal_block(&literal_block[..split_point]));
+    }
+    let copy_literals = &literal_block[split_point..];
+    controls.push(Control::new(
+        Command::new(
+            found.distance as u32,
+            found.length as u16,
+            copy_literals.len() as u8,
+        ),
+        copy_literals.to_vec(),
+    ));
+    literal_block.clear();
+}
+
+/// Reads from an incoming `Read` reader and compresses and encodes to
+/// `Vec<Control>`
+///
+/// Only the bytes from `start` onward are encoded, the bytes before it are only
+/// used as matches for copies.
+pub(crate) fn encode(input: &[u8], start: usize) -> Vec<Control> {
+    let mut controls: Vec<Control> = vec![];
+    let mut searcher = Searcher::new(input, start);
+    let end = searcher.end;
+
+    let mut i = start;
+    let mut literal_block: Vec<u8> = Vec::with_capacity(LITERAL_MAX as usize);
+    while i < end {
+        let matches = searcher.search(i);
+        let Some(found) = matches.best else {
+            push_literal(&mut controls, &mut literal_block, input[i]);
+            i += 1;
+            continue;
+        };
+
+        // only the bytes written from here on matter
+        let here = State {
+            position: i,
+            literals: literal_block.len(),
+            bytes: 0,
+        };
+        let fast = here.copy(found);
+
+        // a literal lets the match at the next position be taken
+        let mut best = None;
+        let mut best_saved = searcher.saved(here.literal(), fast).unwrap_or(0);
+        for length in max(3, found.length.saturating_sub(SHORTEN_WINDOW))..found.length {
+            let Some(shortened) = matches.with_length(length) else {
+                continue;
+            };
+            if let Some(saved) = searcher.saved(here.copy(shortened), fast) {
+                if saved > best_saved {
+                    best = Some(shortened);
+                    best_saved = saved;
+                }
+            }
+        }
+
+        match best {
+            Some(shortened) => {
+                push_copy(&mut controls, &mut literal_block, shortened);
+                i += shortened.length;
+            }
+            None if best_saved > 0 => {
+                push_literal(&mut controls, &mut literal_block, input[i]);
+                i += 1;
+            }
+            None => {
+                push_copy(&mut controls, &mut literal_block, found);
+                i += found.length;
+            }
+        }
+    }
+    // Add remaining literals if there are any
+    if i < input.len() {
+        literal_block.extend_from_slice(&input[i..]);
+    }
+    // Extremely similar to block up above, but with a different control type
+    if literal_block.len() > 3 {
+        let split_point: usize = literal_block.len() - (literal_block.len() % 4);
+        controls.push(Control::new_literal_block(&literal_block[..split_point]));
+        controls.push(Control::new_stop(&literal_block[split_point..]));
+    } else {
+        controls.push(Control::new_stop(&literal_block));
+    }
+
+    controls
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::fast;
+    use crate::data::control::CommandKind;
+    use crate::format::Reference;
+    use crate::{CompressionOptions, easy_compress, easy_decompress};
+
+    /// Amount of bytes the controls take up once written
+    fn encoded_length(controls: &[Control]) -> usize {
+        controls
+            .iter()
+            .map(|control| {
+                let command = match control.command.kind {
+                    CommandKind::Short => 2,
+                    CommandKind::Medium => 3,
+                    CommandKind::Long => 4,
+                    CommandKind::Literal | CommandKind::Stop => 1,
+                };
+                command + control.bytes.len()
+            })
+            .sum()
+    }
+
+    #[proptest]
+    fn symmetrical_compression(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=2_000))] input: Vec<u8>,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, CompressionOptions::Lazy)?;
+        prop_assert_eq!(easy_decompress::<Reference>(&compressed)?, input);
+    }
+
+    #[proptest(ProptestConfig { cases: 1_000, ..ProptestConfig::default() })]
+    fn not_worse_than_fast(
+        #[strategy(1..=4u8)] _symbols: u8,
+        #[strategy(proptest::collection::vec(0..#_symbols, 1..=4_000))] input: Vec<u8>,
+        #[strategy(0..=#input.len() / 2)] start: usize,
+    ) {
+        let lazy = encode(&input, start);
+        let fast = fast::encode(&input, start);
+        prop_assert!(encoded_length(&lazy) <= encoded_length(&fast));
+    }
+
+    #[proptest]
+    fn defers_when_it_pays_off(
+        #[strategy(proptest::sample::subsequence((b'a'..=b'z').collect::<Vec<_>>(), 14..=26))]
+        word: Vec<u8>,
+    ) {
+        // the start of the word only matches a short copy, the rest of it matches a long one. The
+        // word is long enough for the rest of it to take a medium copy either way
+        let input = [&word[..3], b"0", &word[1..], b"1", &word].concat();
+
+        let lazy = encode(&input, 0);
+        let fast = fast::encode(&input, 0);
+        prop_assert!(encoded_length(&lazy) < encoded_length(&fast));
+    }
+
+    #[test]
+    fn defers_to_better_match() {
+        // greedily copying "abc" leaves only the shorter half of the long match
+        let input = b"xabcx1234bcdefghijklmnop5678abcdefghijklmnop";
+
+        let fast = easy_compress::<Reference>(input, CompressionOptions::Fast).unwrap();
+        let lazy = easy_compress::<Reference>(input, CompressionOptions::Lazy).unwrap();
+
+        assert!(lazy.len() < fast.len());
+        assert_eq!(easy_decompress::<Reference>(&lazy).unwrap(), input);
+    }
+}
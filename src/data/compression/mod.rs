@@ -34,20 +34,40 @@
 //! ```
 //!
 //! See [Command] for a specification of control codes
+mod append;
 mod fast;
 mod fastest;
+mod lazy;
 pub(crate) mod match_length;
 mod optimal;
 pub(crate) mod prefix_search;
+mod segmented;
+mod stream;
 
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
+pub use append::append_compress;
+pub use segmented::{SegmentedReport, compress_segmented};
+pub use stream::RefPackEncoder;
+
+use crate::data::batch::thread_count;
 use crate::data::compression::fast::encode;
-use crate::data::compression::optimal::{HASH_CHAINING_LEVELS, encode_slice_hc};
+use crate::data::compression::optimal::{
+    EncoderScratch,
+    HASH_CHAINING_LEVELS,
+    encode_slice_hc,
+    encode_slice_hc_parallel,
+    encode_windowed,
+};
 #[cfg(test)]
 use crate::data::compression::prefix_search::hash_chain::HashChainPrefixSearcher;
-use crate::data::compression::prefix_search::multi_level_hash_chain::MultiLevelPrefixSearcher;
+use crate::data::compression::prefix_search::multi_level_hash_chain::{
+    MultiLevelPrefixSearcher,
+    MultiLevelTables,
+};
+use crate::data::compression::prefix_search::parallel::CHUNK_LENGTH;
 use crate::data::control::{
+    Control,
     LONG_LENGTH_MAX,
     LONG_LENGTH_MIN,
     LONG_OFFSET_MAX,
@@ -58,8 +78,8 @@ use crate::data::control::{
     SHORT_OFFSET_MAX,
 };
 use crate::format::Format;
-use crate::header::Header;
 use crate::header::mode::Mode as HeaderMode;
+use crate::header::{Header, check_length};
 use crate::{RefPackError, RefPackResult};
 
 // used in both fast and high compression algorithms
@@ -110,13 +130,53 @@ pub enum CompressionOptions {
     /// Expect speeds of 5-10MB/s, with compression ratios
     /// that are around 5-10% worse than the [Optimal](CompressionOptions::Optimal) algorithm.
     Fast,
+    /// Algorithm in between [Fast](CompressionOptions::Fast) and
+    /// [Optimal](CompressionOptions::Optimal), which looks ahead before committing to a match
+    ///
+    /// Takes about two to three times as long as [Fast](CompressionOptions::Fast), up to five
+    /// times on data with very few distinct bytes, and never produces a larger output.
+    /// Compression ratios are around 2-4% worse than the [Optimal](CompressionOptions::Optimal)
+    /// algorithm, but on data with very few distinct bytes the gain over
+    /// [Fast](CompressionOptions::Fast) is small.
+    Lazy,
     /// Optimal algorithm intended for data that is read a large amount of times
     ///
     /// Expect speeds of 1-2MB/s
     ///
     /// This algorithm results in compression that is mathematically guaranteed to
     /// produce the smallest possible output for refpack compression.
+    ///
+    /// Inputs larger than a few MiB are searched for matches on all available cores
+    /// while the output is being encoded, which doesn't affect the output. Entries of
+    /// [compress_batch](crate::compress_batch) are searched on the thread compressing them.
     Optimal,
+    /// [Optimal](CompressionOptions::Optimal) algorithm for very large inputs, splitting the input
+    /// into segments that are compressed in parallel on all available cores
+    ///
+    /// Inputs that are too small to be split are compressed identically to
+    /// [Optimal](CompressionOptions::Optimal), as are entries of
+    /// [compress_batch](crate::compress_batch), which are compressed on a single thread.
+    /// Otherwise, output is at most a few bytes per segment larger than
+    /// [Optimal](CompressionOptions::Optimal); see [compress_segmented] for details.
+    OptimalSegmented,
+    /// [Optimal](CompressionOptions::Optimal) algorithm with memory use that doesn't grow with the
+    /// input, for inputs too large to keep the state of every position in memory
+    ///
+    /// The optimal path is committed to, and its controls written, as soon as it is certain, which
+    /// is nearly always within a few KiB. Output is identical to
+    /// [Optimal](CompressionOptions::Optimal), except that the path is committed to regardless
+    /// after 128 KiB, which may make the output a few bytes larger.
+    OptimalWindowed,
+    /// [Fast](CompressionOptions::Fast) algorithm with the effort spent searching for matches
+    /// tuned by [CompressionParams]
+    ///
+    /// [CompressionParams::FAST] produces exactly the same output as
+    /// [Fast](CompressionOptions::Fast), and [CompressionParams::FASTEST] exactly the same output
+    /// as [Fastest](CompressionOptions::Fastest), only slightly slower.
+    ///
+    /// Only the search of [Fast](CompressionOptions::Fast) can be tuned.
+    /// [Lazy](CompressionOptions::Lazy) and the optimal algorithms always search the same way.
+    Custom(CompressionParams),
     /// DO NOT USE IN PRODUCTION
     ///
     /// Produces exactly the same output as [Optimal](CompressionOptions::Optimal),
@@ -126,6 +186,60 @@ pub enum CompressionOptions {
     OptimalReference,
 }
 
+/// Parameters of the match search of [Custom](CompressionOptions::Custom) compression
+///
+/// Searching harder finds better matches at the cost of speed, while limiting
+/// the search trades compression ratio for speed. The default is the same as
+/// [FAST](CompressionParams::FAST).
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(test, derive(test_strategy::Arbitrary))]
+pub struct CompressionParams {
+    /// Maximum amount of earlier positions with the same prefix to check for a
+    /// match at every position
+    #[cfg_attr(test, strategy(1..=0x100usize))]
+    pub chain_depth: usize,
+    /// Whether the positions covered by a copy are remembered as matches for
+    /// later positions, or only the positions a match was searched at
+    pub insert_all_positions: bool,
+    /// Maximum distance back to copy from. Clamped to the 131072 bytes refpack
+    /// can reach.
+    #[cfg_attr(test, strategy(1..=LONG_OFFSET_MAX as usize + 1))]
+    pub max_offset: usize,
+    /// Matches shorter than this are encoded as literals instead. Matches of
+    /// less than 3 bytes are never used.
+    #[cfg_attr(test, strategy(0..=20usize))]
+    pub min_match_length: usize,
+    /// Stop searching at a position as soon as a match of at least this length
+    /// is found, or search through all of `chain_depth` if `None`
+    #[cfg_attr(test, strategy(proptest::option::of(3..=LONG_LENGTH_MAX as usize)))]
+    pub nice_length: Option<usize>,
+}
+
+impl CompressionParams {
+    /// The parameters of [Fast](CompressionOptions::Fast) compression
+    pub const FAST: Self = Self {
+        chain_depth: fast::MAX_HASH_CHAIN_SEARCH_ITERATIONS,
+        insert_all_positions: true,
+        max_offset: LONG_OFFSET_MAX as usize,
+        min_match_length: 3,
+        nice_length: None,
+    };
+    /// The parameters of [Fastest](CompressionOptions::Fastest) compression
+    pub const FASTEST: Self = Self {
+        chain_depth: 1,
+        insert_all_positions: false,
+        max_offset: LONG_OFFSET_MAX as usize,
+        min_match_length: 3,
+        nice_length: None,
+    };
+}
+
+impl Default for CompressionParams {
+    fn default() -> Self {
+        Self::FAST
+    }
+}
+
 /// Compress a data stream from a Reader to refpack format into a Writer.
 ///
 /// First parameter is the length; allows for compressing an arbitrary block
@@ -149,6 +263,8 @@ pub enum CompressionOptions {
 ///
 /// # Errors
 /// - [RefPackError::EmptyInput]: Length provided is 0
+/// - [RefPackError::InputTooLarge]: Input or compressed data is too large for
+///   the header format
 /// - [RefPackError::Io]: Generic IO error when reading or writing
 pub fn compress<F: Format>(
     length: usize,
@@ -175,47 +291,129 @@ pub fn compress<F: Format>(
 ///
 /// # Errors
 /// - [RefPackError::EmptyInput]: Length provided is 0
+/// - [RefPackError::InputTooLarge]: Input or compressed data is too large for
+///   the header format
 /// - [RefPackError::Io]: Generic IO error when reading or writing
 #[inline]
 pub fn easy_compress<F: Format>(
     input: &[u8],
     compression_options: CompressionOptions,
 ) -> Result<Vec<u8>, RefPackError> {
-    let mut writer: Cursor<Vec<u8>> = Cursor::new(vec![]);
+    compress_with_scratch::<F>(
+        input,
+        compression_options,
+        &mut CompressionScratch::default(),
+        0,
+    )
+}
 
-    let length = input.len();
+/// Allocations of compression that can be reused between inputs
+pub(crate) type CompressionScratch = EncoderScratch<MultiLevelTables<HASH_CHAINING_LEVELS>>;
 
+/// [easy_compress], with the allocations of the optimal encoder taken from
+/// `scratch` instead of being made from scratch, using up to `threads` threads
+/// including the calling one. A `threads` of `0` uses as many threads as are
+/// available.
+pub(crate) fn compress_with_scratch<F: Format>(
+    input: &[u8],
+    compression_options: CompressionOptions,
+    scratch: &mut CompressionScratch,
+    threads: usize,
+) -> RefPackResult<Vec<u8>> {
     if input.is_empty() {
         return Err(RefPackError::EmptyInput);
     }
 
+    write_compressed::<F>(input.len(), |writer| {
+        encode_into(input, 0, compression_options, scratch, threads, writer)
+    })
+}
+
+/// Encode the bytes of `input` from `start` onward into controls written to `writer`, with the
+/// bytes before `start` available to copy from as if they had been decompressed already
+///
+/// Up to `threads` threads are used including the calling one, where `0` means as many as are
+/// available
+fn encode_into(
+    input: &[u8],
+    start: usize,
+    compression_options: CompressionOptions,
+    scratch: &mut CompressionScratch,
+    threads: usize,
+    writer: &mut Cursor<Vec<u8>>,
+) -> RefPackResult<()> {
     let controls = match compression_options {
-        CompressionOptions::Fastest => fastest::encode(input),
-        CompressionOptions::Fast => encode(input),
+        CompressionOptions::Fastest => fastest::encode(input, start),
+        CompressionOptions::Fast => encode(input, start),
+        CompressionOptions::Lazy => lazy::encode(input, start),
         CompressionOptions::Optimal => {
-            encode_slice_hc::<MultiLevelPrefixSearcher<{ HASH_CHAINING_LEVELS }>>(input)
+            // the encoder itself takes up a thread, so searching ahead needs at least one more
+            let workers = thread_count(threads) - 1;
+            if workers > 0 && input.len() > 2 * CHUNK_LENGTH {
+                encode_slice_hc_parallel::<MultiLevelPrefixSearcher<{ HASH_CHAINING_LEVELS }>>(
+                    input, start, workers, scratch,
+                )
+            } else {
+                encode_slice_hc::<MultiLevelPrefixSearcher<{ HASH_CHAINING_LEVELS }>>(
+                    input, start, scratch,
+                )
+            }
+        }
+        CompressionOptions::OptimalSegmented => {
+            segmented::encode(input, start, segmented::MIN_SEGMENT_LENGTH, threads).0
         }
+        CompressionOptions::OptimalWindowed => {
+            // writes the controls as soon as they are known, so they never all have to be kept
+            return encode_windowed::<MultiLevelPrefixSearcher<{ HASH_CHAINING_LEVELS }>>(
+                input, start, scratch, writer,
+            );
+        }
+        CompressionOptions::Custom(params) => fast::encode_with(input, start, &params),
         #[cfg(test)]
-        CompressionOptions::OptimalReference => encode_slice_hc::<HashChainPrefixSearcher>(input),
+        CompressionOptions::OptimalReference => {
+            encode_slice_hc::<HashChainPrefixSearcher>(input, start, &mut EncoderScratch::default())
+        }
     };
 
+    for control in &controls {
+        control.write(writer)?;
+    }
+    Ok(())
+}
+
+/// Write the header for `length` bytes of decompressed data, followed by `controls`
+fn write_controls<F: Format>(length: usize, controls: &[Control]) -> RefPackResult<Vec<u8>> {
+    write_compressed::<F>(length, |writer| {
+        for control in controls {
+            control.write(writer)?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the header for `length` bytes of decompressed data, followed by the controls written by
+/// `write_controls`
+fn write_compressed<F: Format>(
+    length: usize,
+    write_controls: impl FnOnce(&mut Cursor<Vec<u8>>) -> RefPackResult<()>,
+) -> RefPackResult<Vec<u8>> {
+    // checked up front as well, so no time is spent compressing input that can't be written
+    check_length(F::HeaderMode::MAX_DECOMPRESSED_LENGTH, length)?;
+
+    let mut writer: Cursor<Vec<u8>> = Cursor::new(vec![]);
+
     let header_length = F::HeaderMode::length(length);
 
     let header_position = writer.stream_position()?;
     let data_start_pos = writer.seek(SeekFrom::Current(header_length as i64))?;
 
-    for control in controls {
-        control.write(&mut writer)?;
-    }
+    write_controls(&mut writer)?;
 
     let data_end_pos = writer.stream_position()?;
 
     let compression_length = data_end_pos - data_start_pos;
 
-    let header = Header {
-        compressed_length: Some(compression_length as u32),
-        decompressed_length: length as u32,
-    };
+    let header = Header::with_lengths::<F::HeaderMode>(length, compression_length as usize)?;
 
     writer.seek(SeekFrom::Start(header_position))?;
 
@@ -229,7 +427,8 @@ mod test {
     use test_strategy::proptest;
 
     use super::*;
-    use crate::format::Reference;
+    use crate::easy_decompress;
+    use crate::format::{Maxis, Reference};
 
     #[proptest]
     #[ignore]
@@ -259,6 +458,48 @@ mod test {
         assert!(matches!(result.unwrap_err(), RefPackError::EmptyInput));
     }
 
+    #[test]
+    fn too_large_input_yields_error() {
+        let input = vec![0; 0x1_00_00_00];
+        let result = easy_compress::<Maxis>(&input, CompressionOptions::Fastest);
+        assert!(matches!(
+            result.unwrap_err(),
+            RefPackError::InputTooLarge {
+                max: 0xFF_FF_FF,
+                got: 0x1_00_00_00
+            }
+        ));
+    }
+
+    #[proptest]
+    fn custom_presets_match_options(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=2_000))] input: Vec<u8>,
+    ) {
+        prop_assert_eq!(
+            easy_compress::<Reference>(
+                &input,
+                CompressionOptions::Custom(CompressionParams::FAST)
+            )?,
+            easy_compress::<Reference>(&input, CompressionOptions::Fast)?
+        );
+        prop_assert_eq!(
+            easy_compress::<Reference>(
+                &input,
+                CompressionOptions::Custom(CompressionParams::FASTEST)
+            )?,
+            easy_compress::<Reference>(&input, CompressionOptions::Fastest)?
+        );
+    }
+
+    #[proptest]
+    fn custom_round_trip(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=2_000))] input: Vec<u8>,
+        params: CompressionParams,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, CompressionOptions::Custom(params))?;
+        prop_assert_eq!(easy_decompress::<Reference>(&compressed)?, input);
+    }
+
     #[proptest]
     #[ignore]
     fn optimal_matches_reference(
@@ -5,16 +5,50 @@
 //                                                                             /
 ////////////////////////////////////////////////////////////////////////////////
 
-use std::array;
 use std::cmp::min;
+use std::io::Cursor;
 use std::ops::Range;
+use std::{array, mem};
 
+use crate::RefPackResult;
 use crate::data::compression::bytes_for_match;
-use crate::data::compression::prefix_search::PrefixSearcher;
-use crate::data::control::{COPY_LITERAL_MAX, Command, CommandKind, Control, LITERAL_MAX};
+use crate::data::compression::prefix_search::parallel::{self, CHUNK_LENGTH};
+use crate::data::compression::prefix_search::{MatchFinder, PrefixSearcher};
+use crate::data::control::{
+    COPY_LITERAL_MAX,
+    Command,
+    CommandKind,
+    Control,
+    LITERAL_MAX,
+    LONG_LENGTH_MAX,
+    LONG_OFFSET_MAX,
+};
 
 pub(crate) const HASH_CHAINING_LEVELS: usize = 4;
 
+/// The most bytes a single command can encode, its literals and its copy combined
+const COMMAND_SPAN: usize = LONG_LENGTH_MAX as usize + COPY_LITERAL_MAX as usize;
+
+/// Amount of states [encode_windowed] keeps before committing to a path that isn't known to be
+/// optimal
+const MAX_WINDOW: usize = LONG_OFFSET_MAX as usize;
+
+/// Amount of positions between the attempts of [encode_windowed] to commit to a path
+const COMMIT_INTERVAL: usize = 4096;
+
+/// Allocations made by [encode_slice_hc], kept around so encoding several inputs doesn't have to
+/// allocate them again every time
+#[derive(Default)]
+pub(crate) struct EncoderScratch<T> {
+    tables: T,
+    command_state: Vec<u32>,
+    cost_state: Vec<u32>,
+    /// Keep the cost states through the traceback instead of freeing them before it, so they
+    /// don't have to be allocated again for the next input. This raises peak memory use by 4 bytes
+    /// per input byte, which is only worth it when many inputs are encoded one after another.
+    pub(crate) keep_cost_state: bool,
+}
+
 // state is packed into 32 bits for SIMD optimization purposes
 // 31: literal/copy command flag
 // when 0:
@@ -46,6 +80,24 @@ impl CommandState {
         if self.is_literal() { self.0 as u8 } else { 0 }
     }
 
+    /// The amount of encoded bytes before this state that the path leading up to it goes through
+    ///
+    /// Literals that aren't a multiple of 4 are taken along by the command after them, so the
+    /// path goes through the state before them. The other literals are written as a literal
+    /// command of their own.
+    fn span(self) -> usize {
+        if self.is_literal() {
+            let literals = self.num_literals() as usize;
+            if literals % 4 != 0 {
+                literals % 4
+            } else {
+                literals
+            }
+        } else {
+            ((self.0 & ((1 << 11) - 1)) + ((self.0 >> 11) & 3)) as usize
+        }
+    }
+
     fn to_command(self) -> Command {
         if self.is_literal() {
             Command::new_literal((self.0 & 0xFF) as u8)
@@ -60,26 +112,57 @@ impl CommandState {
 }
 
 fn controls_from_state_slice(state: &[u32], input: &[u8]) -> Vec<Control> {
-    let mut cur_pos = state.len() - 1;
+    let cur_pos = state.len() - 1;
     // add the output controls in reverse order in this list
     let mut controls = vec![];
 
+    let literal_pos = push_stop(CommandState(state[cur_pos]), cur_pos, input, &mut controls);
+    push_controls(
+        |pos| CommandState(state[pos]),
+        0,
+        literal_pos,
+        input,
+        &mut controls,
+    );
+
+    // we built the controls in reverse order, so reverse the vec
+    controls.reverse();
+
+    controls
+}
+
+/// Push the stop command ending at `end`, which has the state `state`
+///
+/// Returns the number of encoded bytes before the literals of the stop command
+fn push_stop(state: CommandState, end: usize, input: &[u8], controls: &mut Vec<Control>) -> usize {
     // special handling of the last literals: the last command must be a stop command
     // so we can take the number of literals at the end of the input and put them into the stop command
-    let num_stop_literals = CommandState(state[cur_pos]).num_literals() % 4;
+    let num_stop_literals = state.num_literals() % 4;
 
-    // the current position includes the last byte of this literal, so subtract one
-    let literal_pos = cur_pos + 1 - num_stop_literals as usize;
+    let literal_pos = end - num_stop_literals as usize;
     controls.push(Control {
         command: Command::new_stop_unchecked(num_stop_literals),
-        bytes: input[literal_pos..literal_pos + num_stop_literals as usize].to_vec(),
+        bytes: input[literal_pos..end].to_vec(),
     });
 
-    cur_pos -= num_stop_literals as usize;
+    literal_pos
+}
+
+/// Push the controls on the path from the state `base` up to the state `end` in reverse order,
+/// with the states given by `state_at`
+fn push_controls(
+    state_at: impl Fn(usize) -> CommandState,
+    base: usize,
+    end: usize,
+    input: &[u8],
+    controls: &mut Vec<Control>,
+) {
+    // the number of bytes encoded by the commands up to the current state
+    let mut cur_pos = end;
 
-    loop {
+    while cur_pos > base {
         // the bytes of the next command end at the current position
-        let cur_command = CommandState(state[cur_pos]).to_command();
+        let cur_command = state_at(cur_pos).to_command();
 
         if let CommandKind::Literal = cur_command.kind {
             assert_eq!(cur_command.literal % 4, 0);
@@ -92,24 +175,14 @@ fn controls_from_state_slice(state: &[u32], input: &[u8]) -> Vec<Control> {
         let command_decompressed_bytes = num_literal + num_copy;
 
         // same as with the stop command
-        let literal_pos = cur_pos + 1 - command_decompressed_bytes;
+        let literal_pos = cur_pos - command_decompressed_bytes;
         controls.push(Control {
             command: cur_command,
             bytes: input[literal_pos..literal_pos + num_literal].to_vec(),
         });
 
-        if command_decompressed_bytes > cur_pos {
-            // the encoding should end at position -1, but unsigned integers cannot represent this
-            debug_assert!(command_decompressed_bytes == cur_pos + 1);
-            break;
-        }
-        cur_pos -= command_decompressed_bytes;
+        cur_pos = literal_pos;
     }
-
-    // we built the controls in reverse order, so reverse the vec
-    controls.reverse();
-
-    controls
 }
 
 fn update_state_simd(
@@ -193,122 +266,514 @@ fn update_state_simd(
 /// Once all positions have been opened it is known that the last cost state is the minimum cost
 /// for encoding all bytes in the input. It is then possible to encode all commands by tracing backwards
 /// through the input while referencing the command state that is built in the search process.
-pub(crate) fn encode_slice_hc<'a, PS: PrefixSearcher<'a>>(input: &'a [u8]) -> Vec<Control> {
-    let input_length = input.len();
-
+///
+/// Only the bytes of `input` from `start` onward are encoded. The bytes before `start` are allowed
+/// to be referenced by copies as if they were decompressed output that came before the encoded bytes.
+///
+/// All allocations are taken from `scratch` and given back to it afterwards, so they can be reused
+/// when encoding many inputs.
+pub(crate) fn encode_slice_hc<'a, PS: PrefixSearcher<'a>>(
+    input: &'a [u8],
+    start: usize,
+    scratch: &mut EncoderScratch<PS::Tables>,
+) -> Vec<Control> {
     // if the input is 3 bytes or fewer it is impossible to encode any copy commands
     // just return the stop commands with the input as literal bytes
-    if input_length <= 3 {
+    if input.len() <= 3 {
         return vec![Control {
-            command: Command::new_stop_unchecked(input_length as u8),
-            bytes: Vec::from(input),
+            command: Command::new_stop_unchecked((input.len() - start) as u8),
+            bytes: Vec::from(&input[start..]),
         }];
     }
 
-    // build the prefix searcher
-    // it will give us all previous occurrences of the current position along with their match length
-    let mut prev = PS::build(input);
+    let mut prev = primed_searcher::<PS>(input, start, mem::take(&mut scratch.tables));
+    let controls = parse(input, start, &mut prev, scratch);
+
+    scratch.tables = prev.into_tables();
+
+    controls
+}
+
+/// Build the prefix searcher over `input`, reusing the allocations in `tables`
+///
+/// It will give us all previous occurrences of the current position along with their match length.
+/// The searcher has to see every position in order, so it is fed the positions before `start`
+/// without looking at the matches, as nothing before the start has to be encoded.
+fn primed_searcher<'a, PS: PrefixSearcher<'a>>(
+    input: &'a [u8],
+    start: usize,
+    tables: PS::Tables,
+) -> PS {
+    let mut prev = PS::build(input, tables);
+    for pos in 1..min(start, input.len() - 2) {
+        prev.search(pos, |_, _, _| {});
+    }
+    prev
+}
+
+/// [encode_slice_hc], with the matches searched for ahead on `workers` threads while the encoder
+/// consumes them. Output is identical to [encode_slice_hc].
+pub(crate) fn encode_slice_hc_parallel<'a, PS: PrefixSearcher<'a>>(
+    input: &'a [u8],
+    start: usize,
+    workers: usize,
+    scratch: &mut EncoderScratch<PS::Tables>,
+) -> Vec<Control> {
+    if input.len() <= 3 {
+        return encode_slice_hc::<PS>(input, start, scratch);
+    }
+
+    parallel::with_parallel_matches::<PS, _>(input, CHUNK_LENGTH, workers, |matches| {
+        parse(input, start, matches, scratch)
+    })
+}
+
+/// Find the cheapest controls to encode the bytes of `input` from `start` onward with the matches
+/// reported by `finder`, which has to report them as if it was a [PrefixSearcher] that has already
+/// searched all positions before `start`
+fn parse<T>(
+    input: &[u8],
+    start: usize,
+    finder: &mut impl MatchFinder,
+    scratch: &mut EncoderScratch<T>,
+) -> Vec<Control> {
+    let encoded = &input[start..];
+    let encoded_length = encoded.len();
+
+    // both state vecs are indexed by the number of encoded bytes,
+    // so the state at index `i` is the last command to encode the first `i` bytes
 
     // tracks the last command to encode all bytes in the input up to a certain point
-    let mut command_state = vec![CommandState::default().0; input_length];
+    let command_state = &mut scratch.command_state;
+    command_state.clear();
+    command_state.resize(encoded_length + 1, CommandState::default().0);
     // tracks the maximum cost to encode all bytes in the input up to a certain position
-    let mut cost_state = vec![u32::MAX; input_length];
+    let cost_state = &mut scratch.cost_state;
+    cost_state.clear();
+    cost_state.resize(encoded_length + 1, u32::MAX);
     // the state vecs could be combined into a single vec, but we store them separately for SIMD purposes
 
-    // we know the first byte must be encoded as a literal, thus the cost is 1
-    cost_state[0] = 1;
-    // idem
-    command_state[0] = CommandState::literal(1).0;
+    // encoding nothing costs nothing
+    cost_state[0] = 0;
+    command_state[0] = CommandState::literal(0).0;
 
     // go through all the byte positions in the input
-    for pos in 0..(input_length as u32 - 1) {
-        // since this position has no unexplored predecessors
-        // we know the cost to reach this byte is equivalent to the stored cost state
-        let cur_cost = cost_state[pos as usize];
-        // and the command to reach that state is the stored command
-        let cur_command = command_state[pos as usize];
-        // get the number of literals that are passed on into the next command
-        // for copy commands this is always 0
-        let cur_literals = CommandState(cur_command).num_literals();
-
-        // there can't be any matches on the last 3 bytes since matches must always be at least 3 bytes
-        if pos < (input_length - 3) as u32 {
-            // we want to try encoding bytes for the next byte onward since the current position is already known
-            // so the search position is the current position + 1
-            let match_start_pos = pos + 1;
-
-            // search for all matches with the search position
-            prev.search(
-                match_start_pos as usize,
-                |match_pos, match_start, match_end| {
-                    // for all bytes in this match, update the command and cost state
-                    // for all positions that have a lower cost than the stored cost state
-
-                    debug_assert!(match_start < match_end);
-
-                    let offset = match_start_pos as usize - match_pos;
-
-                    // loop through all ranges in the match that have an equal cost
-                    // for SIMD optimization purposes
-                    let mut i = match_start;
-                    while i < match_end {
-                        if let Some((command_bytes, interval_limit)) = bytes_for_match(i, offset) {
-                            // get the cost to encode the current command (command_bytes)
-                            if let Some(command_bytes) = command_bytes {
-                                let match_length_start = i;
-                                let match_length_end = min(interval_limit + 1, match_end);
-                                let range = (pos as usize + match_length_start)
-                                    ..(pos as usize + match_length_end);
-
-                                // the cost for all encoded commands in this range
-                                let new_cost = cur_cost + command_bytes as u32;
-
-                                // now update the cost and command state in this range with the new cost
-                                update_state_simd(
-                                    &mut cost_state,
-                                    &mut command_state,
-                                    new_cost,
-                                    range,
-                                    CommandState::command(
-                                        offset as u32,
-                                        cur_literals % 4,
-                                        match_length_start as u16,
-                                    ),
-                                );
-                            }
-
-                            i = interval_limit + 1;
-                            continue;
-                        }
-                        break;
-                    }
-                },
-            );
+    for pos in 0..encoded_length {
+        expand(cost_state, command_state, pos, input, start + pos, finder);
+    }
+
+    // since we don't need the cost state for building the output command list
+    // we can drop it early to save on peak memory usage
+    if !scratch.keep_cost_state {
+        scratch.cost_state = vec![];
+    }
+
+    // trace backwards through the command state to extract the output command list
+    controls_from_state_slice(&scratch.command_state, encoded)
+}
+
+/// [encode_slice_hc] with memory that doesn't grow with the input, writing the controls to
+/// `writer` as it goes instead of returning them.
+///
+/// Only the states of the last [MAX_WINDOW] positions or so are kept. Every [COMMIT_INTERVAL]
+/// positions, the paths of all states that a command could still continue from are traced back to
+/// the first state they all go through. Whatever comes after, the optimal path is certain to go
+/// through that state, so the controls up to it are written and the states before it are dropped.
+/// This produces exactly the same output as [encode_slice_hc] unless the paths don't come together
+/// within [MAX_WINDOW] positions, in which case the path to the current state is committed to,
+/// making the output slightly worse than optimal.
+pub(crate) fn encode_windowed<'a, PS: PrefixSearcher<'a>>(
+    input: &'a [u8],
+    start: usize,
+    scratch: &mut EncoderScratch<PS::Tables>,
+    writer: &mut Cursor<Vec<u8>>,
+) -> RefPackResult<()> {
+    if input.len() <= 3 {
+        for control in encode_slice_hc::<PS>(input, start, scratch) {
+            control.write(writer)?;
         }
+        return Ok(());
+    }
 
-        // update for the next literal
-        let mut literal_cost = cur_cost;
-        literal_cost += 1;
-        let mut new_state_literal = cur_literals + 1;
-        if new_state_literal > (LITERAL_MAX + COPY_LITERAL_MAX) {
-            // literal + copy command cannot represent this amount, wrap back around to a new literal command
-            new_state_literal = 4;
+    let mut prev = primed_searcher::<PS>(input, start, mem::take(&mut scratch.tables));
+    let result = parse_windowed(input, start, MAX_WINDOW, &mut prev, scratch, writer);
+    scratch.tables = prev.into_tables();
+
+    result
+}
+
+/// The states of the positions that haven't been committed to yet
+struct Window<'a> {
+    command_state: &'a mut Vec<u32>,
+    cost_state: &'a mut Vec<u32>,
+    /// the position of the first state in the window
+    offset: usize,
+    /// the last state that every path is known to go through, controls have been written up to it
+    base: usize,
+    /// states visited while looking for the first state all paths go through,
+    /// marked with the current `stamp`
+    marks: Vec<u32>,
+    stamp: u32,
+}
+
+impl Window<'_> {
+    fn state(&self, pos: usize) -> CommandState {
+        CommandState(self.command_state[pos - self.offset])
+    }
+
+    fn predecessor(&self, pos: usize) -> usize {
+        pos - self.state(pos).span()
+    }
+
+    /// The last state before `pos` that ends a command. Literals that aren't a multiple of 4 can
+    /// only be written along with the command after them.
+    fn boundary(&self, pos: usize) -> usize {
+        pos - (self.state(pos).num_literals() % 4) as usize
+    }
+
+    /// Mark the state at `pos`, returning whether it was marked already
+    fn mark(&mut self, pos: usize) -> bool {
+        let mark = &mut self.marks[pos - self.offset];
+        mem::replace(mark, self.stamp) == self.stamp
+    }
+
+    /// Move the window to start at `base`, making room for the states after it
+    fn slide(&mut self) {
+        let dropped = self.base - self.offset;
+        let length = self.cost_state.len();
+
+        self.cost_state.copy_within(dropped.., 0);
+        self.cost_state[length - dropped..].fill(u32::MAX);
+        self.command_state.copy_within(dropped.., 0);
+        self.command_state[length - dropped..].fill(CommandState::default().0);
+        self.offset = self.base;
+    }
+
+    /// Find the last state that the paths of all states from `from` up to `to` go through
+    fn common_predecessor(&mut self, from: usize, to: usize) -> usize {
+        self.stamp += 1;
+
+        // every marked state has a path through `common`
+        let mut common = to;
+        self.mark(common);
+        for from_pos in (from..to).rev() {
+            // follow the path until it joins a path known to go through `common`
+            let mut pos = from_pos;
+            while pos > common && !self.mark(pos) {
+                pos = self.predecessor(pos);
+            }
+            if pos >= common {
+                continue;
+            }
+
+            // the path went past `common` without going through it,
+            // so the paths only come together where they meet further back
+            while pos != common {
+                if pos > common {
+                    pos = self.predecessor(pos);
+                    self.mark(pos);
+                } else {
+                    common = self.predecessor(common);
+                    self.mark(common);
+                }
+            }
         }
-        if new_state_literal == 4 {
-            // a copy command cannot represent this, so we needed to add a new byte for the literal command
-            literal_cost += 1;
+
+        common
+    }
+
+    /// Write the controls on the path from `base` up to `end`, and make `end` the new base
+    fn commit(
+        &mut self,
+        end: usize,
+        encoded: &[u8],
+        writer: &mut Cursor<Vec<u8>>,
+    ) -> RefPackResult<()> {
+        let mut controls = vec![];
+        push_controls(
+            |pos| self.state(pos),
+            self.base,
+            end,
+            encoded,
+            &mut controls,
+        );
+        for control in controls.iter().rev() {
+            control.write(writer)?;
         }
 
-        if cost_state[pos as usize + 1] > literal_cost {
-            cost_state[pos as usize + 1] = literal_cost;
-            command_state[pos as usize + 1] = CommandState::literal(new_state_literal).0;
+        self.base = end;
+        Ok(())
+    }
+}
+
+/// [parse] with only the states after the last state that was committed to kept around, which is
+/// never more than around `max_window` states before the current one
+fn parse_windowed<T>(
+    input: &[u8],
+    start: usize,
+    max_window: usize,
+    finder: &mut impl MatchFinder,
+    scratch: &mut EncoderScratch<T>,
+    writer: &mut Cursor<Vec<u8>>,
+) -> RefPackResult<()> {
+    let encoded = &input[start..];
+    let encoded_length = encoded.len();
+    let window_length = min(encoded_length + 1, 2 * max_window + COMMAND_SPAN);
+
+    scratch.command_state.clear();
+    scratch
+        .command_state
+        .resize(window_length, CommandState::default().0);
+    scratch.cost_state.clear();
+    scratch.cost_state.resize(window_length, u32::MAX);
+
+    let mut window = Window {
+        command_state: &mut scratch.command_state,
+        cost_state: &mut scratch.cost_state,
+        offset: 0,
+        base: 0,
+        marks: vec![0; window_length],
+        stamp: 0,
+    };
+
+    // encoding nothing costs nothing
+    window.cost_state[0] = 0;
+    window.command_state[0] = CommandState::literal(0).0;
+
+    let mut next_commit = COMMIT_INTERVAL;
+    for pos in 0..encoded_length {
+        if pos >= next_commit {
+            next_commit = pos + COMMIT_INTERVAL;
+
+            // every path from here on goes through one of the states a single command before it
+            let from = pos.saturating_sub(COMMAND_SPAN).max(window.base);
+            let common = window.common_predecessor(from, pos);
+            window.commit(window.boundary(common), encoded, writer)?;
+        }
+
+        if pos - window.base >= max_window && window.boundary(pos) == pos {
+            // the paths didn't come together, so settle for the path to this state,
+            // which means every state after it has to be reached from this state alone
+            window.commit(pos, encoded, writer)?;
+            if window.state(pos).is_literal() {
+                window.command_state[pos - window.offset] = CommandState::literal(0).0;
+            }
+            let end = min(pos + COMMAND_SPAN, encoded_length) + 1;
+            let end = min(end, window.offset + window_length);
+            window.cost_state[pos + 1 - window.offset..end - window.offset].fill(u32::MAX);
+        }
+
+        if min(pos + LONG_LENGTH_MAX as usize + 1, encoded_length) >= window.offset + window_length
+        {
+            window.slide();
         }
+
+        let local_pos = pos - window.offset;
+        expand(
+            window.cost_state,
+            window.command_state,
+            local_pos,
+            input,
+            start + pos,
+            finder,
+        );
     }
 
-    // since we don't need the cost state for building the output command list
-    // we can drop it early to save on peak memory usage
-    drop(cost_state);
+    let mut controls = vec![];
+    let end = encoded_length;
+    let literal_pos = push_stop(window.state(end), end, encoded, &mut controls);
+    push_controls(
+        |pos| window.state(pos),
+        window.base,
+        literal_pos,
+        encoded,
+        &mut controls,
+    );
+    for control in controls.iter().rev() {
+        control.write(writer)?;
+    }
 
-    // trace backwards through the command state to extract the output command list
-    controls_from_state_slice(&command_state, input)
+    Ok(())
+}
+
+/// Open the state at `pos`: since it has no unexplored predecessors, its cost is final, and the
+/// cost of every state reachable from it with a single command can be updated.
+///
+/// `input_pos` is the position in `input` of the byte after the state, which is the next byte to
+/// be encoded.
+fn expand(
+    cost_state: &mut [u32],
+    command_state: &mut [u32],
+    pos: usize,
+    input: &[u8],
+    input_pos: usize,
+    finder: &mut impl MatchFinder,
+) {
+    // since this position has no unexplored predecessors
+    // we know the cost to reach this byte is equivalent to the stored cost state
+    let cur_cost = cost_state[pos];
+    // and the command to reach that state is the stored command
+    let cur_command = command_state[pos];
+    // get the number of literals that are passed on into the next command
+    // for copy commands this is always 0
+    let cur_literals = CommandState(cur_command).num_literals();
+
+    // there can't be any matches on the last 3 bytes since matches must always be at least 3 bytes,
+    // and the first byte of the input can't match anything
+    if input_pos > 0 && input_pos + 3 <= input.len() {
+        // search for all matches with the search position
+        finder.find(input_pos, |match_pos, match_start, match_end| {
+            // for all bytes in this match, update the command and cost state
+            // for all positions that have a lower cost than the stored cost state
+
+            debug_assert!(match_start < match_end);
+
+            let offset = input_pos - match_pos;
+
+            // loop through all ranges in the match that have an equal cost
+            // for SIMD optimization purposes
+            let mut i = match_start;
+            while i < match_end {
+                if let Some((command_bytes, interval_limit)) = bytes_for_match(i, offset) {
+                    // get the cost to encode the current command (command_bytes)
+                    if let Some(command_bytes) = command_bytes {
+                        let match_length_start = i;
+                        let match_length_end = min(interval_limit + 1, match_end);
+                        let range = (pos + match_length_start)..(pos + match_length_end);
+
+                        // the cost for all encoded commands in this range
+                        let new_cost = cur_cost + command_bytes as u32;
+
+                        // now update the cost and command state in this range with the new cost
+                        update_state_simd(
+                            cost_state,
+                            command_state,
+                            new_cost,
+                            range,
+                            CommandState::command(
+                                offset as u32,
+                                cur_literals % 4,
+                                match_length_start as u16,
+                            ),
+                        );
+                    }
+
+                    i = interval_limit + 1;
+                    continue;
+                }
+                break;
+            }
+        });
+    }
+
+    // update for the next literal
+    let mut literal_cost = cur_cost;
+    literal_cost += 1;
+    let mut new_state_literal = cur_literals + 1;
+    if new_state_literal > (LITERAL_MAX + COPY_LITERAL_MAX) {
+        // literal + copy command cannot represent this amount, wrap back around to a new literal command
+        new_state_literal = 4;
+    }
+    if new_state_literal == 4 {
+        // a copy command cannot represent this, so we needed to add a new byte for the literal command
+        literal_cost += 1;
+    }
+
+    if cost_state[pos + 1] > literal_cost {
+        cost_state[pos + 1] = literal_cost;
+        command_state[pos + 1] = CommandState::literal(new_state_literal).0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::prefix_search::multi_level_hash_chain::MultiLevelPrefixSearcher;
+    use crate::data::compression::{write_compressed, write_controls};
+    use crate::easy_decompress;
+    use crate::format::Reference;
+
+    type Searcher<'a> = MultiLevelPrefixSearcher<'a, HASH_CHAINING_LEVELS>;
+
+    fn windowed(input: &[u8], max_window: usize) -> Vec<u8> {
+        write_compressed::<Reference>(input.len(), |writer| {
+            let mut prev = primed_searcher::<Searcher>(input, 0, Default::default());
+            parse_windowed(
+                input,
+                0,
+                max_window,
+                &mut prev,
+                &mut EncoderScratch::<()>::default(),
+                writer,
+            )
+        })
+        .unwrap()
+    }
+
+    #[proptest]
+    fn parallel_matches_sequential(
+        #[strategy(proptest::collection::vec(0..=3u8, 4..=2_000))] input: Vec<u8>,
+        #[strategy(50..=500usize)] chunk_length: usize,
+        #[strategy(1..=4usize)] workers: usize,
+    ) {
+        let mut scratch = EncoderScratch::<()>::default();
+        let parallel = parallel::with_parallel_matches::<Searcher, _>(
+            &input,
+            chunk_length,
+            workers,
+            |matches| parse(&input, 0, matches, &mut scratch),
+        );
+
+        prop_assert_eq!(
+            parallel,
+            encode_slice_hc::<Searcher>(&input, 0, &mut EncoderScratch::default())
+        );
+    }
+
+    #[proptest]
+    fn windowed_matches_optimal(
+        // long enough to commit a few times
+        #[strategy(proptest::collection::vec(0..=3u8, 4..=3 * COMMIT_INTERVAL))] input: Vec<u8>,
+    ) {
+        let optimal = encode_slice_hc::<Searcher>(&input, 0, &mut EncoderScratch::default());
+
+        prop_assert_eq!(
+            windowed(&input, MAX_WINDOW),
+            write_controls::<Reference>(input.len(), &optimal)?
+        );
+    }
+
+    #[proptest]
+    fn windowed_commits_when_paths_stay_apart(
+        #[strategy(proptest::collection::vec(0..=3u8, 4..=5_000))] input: Vec<u8>,
+        #[strategy(1..=1_000usize)] max_window: usize,
+    ) {
+        let compressed = windowed(&input, max_window);
+
+        prop_assert_eq!(easy_decompress::<Reference>(&compressed)?, input);
+    }
+
+    #[test]
+    fn windowed_memory_is_bounded() {
+        // long enough for the window to slide
+        let input: Vec<u8> = (0..(MAX_WINDOW * 3) as u32)
+            .map(|i| ((i % 251) ^ (i / 97)) as u8)
+            .collect();
+        let mut scratch = EncoderScratch::default();
+        let mut writer = Cursor::new(vec![]);
+
+        encode_windowed::<Searcher>(&input, 0, &mut scratch, &mut writer).unwrap();
+
+        assert!(scratch.cost_state.len() <= 2 * MAX_WINDOW + COMMAND_SPAN);
+        assert!(scratch.command_state.len() <= 2 * MAX_WINDOW + COMMAND_SPAN);
+        let optimal = encode_slice_hc::<Searcher>(&input, 0, &mut EncoderScratch::default());
+        let mut expected = Cursor::new(vec![]);
+        for control in &optimal {
+            control.write(&mut expected).unwrap();
+        }
+        assert!(writer.into_inner() == expected.into_inner());
+    }
 }
@@ -47,6 +47,19 @@ impl HashChain {
             cur_position: found_position,
         })
     }
+
+    /// The positions [insert](HashChain::insert) returned when `position` was
+    /// inserted. Only holds while `position` is less than
+    /// [HASH_CHAIN_BUFFER_SIZE] positions behind the last inserted position.
+    pub fn chain(&self, position: u32) -> impl Iterator<Item = u32> + use<'_> {
+        let found_position = Some(self.hash_chain[position as usize % HASH_CHAIN_BUFFER_SIZE])
+            .filter(|&pos| pos != u32::MAX);
+        found_position.into_iter().chain(HashChainIter {
+            hash_chain: self,
+            orig_position: position,
+            cur_position: found_position,
+        })
+    }
 }
 
 pub(crate) struct HashChainIter<'a> {
@@ -81,7 +94,10 @@ pub(crate) struct HashChainPrefixSearcher<'a> {
 
 #[cfg(test)]
 impl<'a> PrefixSearcher<'a> for HashChainPrefixSearcher<'a> {
-    fn build(buffer: &'a [u8]) -> Self {
+    // only used as a reference, so not worth reusing
+    type Tables = ();
+
+    fn build(buffer: &'a [u8], (): ()) -> Self {
         let mut hash_chain = HashChain::new(buffer.len());
 
         let _ = hash_chain.insert(prefix(buffer), 0);
@@ -89,6 +105,8 @@ impl<'a> PrefixSearcher<'a> for HashChainPrefixSearcher<'a> {
         Self { buffer, hash_chain }
     }
 
+    fn into_tables(self) {}
+
     fn search<F: FnMut(usize, usize, usize)>(&mut self, pos: usize, mut found_fn: F) {
         let mut min_length = 2;
         self.hash_chain
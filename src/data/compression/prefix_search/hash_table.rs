@@ -26,6 +26,19 @@ impl PrefixTable {
         }
     }
 
+    /// Empty the table to be used for `bytes` bytes, keeping its allocations
+    /// if it's the right kind of table for that amount
+    pub(crate) fn reuse(mut self, bytes: usize) -> Self {
+        match self {
+            PrefixTable::Small(ref mut table) if bytes < SMALL_TABLE_CUTOFF => table.clear(),
+            PrefixTable::Large(ref mut table) if bytes >= SMALL_TABLE_CUTOFF => {
+                table.table.iter_mut().for_each(Vec::clear);
+            }
+            _ => return Self::new(bytes),
+        }
+        self
+    }
+
     pub(crate) fn insert(&mut self, prefix: [u8; 3], position: u32) -> Option<u32> {
         match *self {
             PrefixTable::Small(ref mut table) => {
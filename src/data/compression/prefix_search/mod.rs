@@ -8,6 +8,7 @@
 pub(crate) mod hash_chain;
 pub(crate) mod hash_table;
 pub(crate) mod multi_level_hash_chain;
+pub(crate) mod parallel;
 
 pub(crate) fn prefix(input_buf: &[u8]) -> [u8; 3] {
     let buf: &[u8] = &input_buf[..3];
@@ -15,7 +16,14 @@ pub(crate) fn prefix(input_buf: &[u8]) -> [u8; 3] {
 }
 
 pub(crate) trait PrefixSearcher<'a> {
-    fn build(buffer: &'a [u8]) -> Self;
+    /// Allocations of the searcher that can be reused to search another buffer
+    type Tables: Default;
+
+    /// Build a searcher over `buffer`, reusing the allocations in `tables`
+    fn build(buffer: &'a [u8], tables: Self::Tables) -> Self;
+
+    /// Give back the allocations of the searcher once it is done
+    fn into_tables(self) -> Self::Tables;
 
     /// Search for all increasingly large matches in the search buffer.
     ///
@@ -28,6 +36,18 @@ pub(crate) trait PrefixSearcher<'a> {
     fn search<F: FnMut(usize, usize, usize)>(&mut self, pos: usize, found_fn: F);
 }
 
+/// Anything that reports the matches at every position in order, the same way
+/// [PrefixSearcher::search] does
+pub(crate) trait MatchFinder {
+    fn find<F: FnMut(usize, usize, usize)>(&mut self, pos: usize, found_fn: F);
+}
+
+impl<'a, PS: PrefixSearcher<'a>> MatchFinder for PS {
+    fn find<F: FnMut(usize, usize, usize)>(&mut self, pos: usize, found_fn: F) {
+        self.search(pos, found_fn);
+    }
+}
+
 // optimization: we only have to reserve LONG_OFFSET_MAX + 1 bytes
 // but since it costs less instructions to do modulo by a power of two
 // we'll use the next largest power of two
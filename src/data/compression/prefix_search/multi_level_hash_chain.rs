@@ -76,9 +76,12 @@ struct MultiLevelHashChain<const N: usize> {
 }
 
 impl<const N: usize> MultiLevelHashChain<N> {
-    fn new(bytes: usize) -> Self {
+    /// Create a hash chain for `bytes` bytes, reusing the allocation of `data`
+    fn new(mut data: Vec<HashChainLink<N>>, bytes: usize) -> Self {
+        data.clear();
+        data.resize(min(bytes, HASH_CHAIN_BUFFER_SIZE), HashChainLink::default());
         Self {
-            data: vec![HashChainLink::default(); min(bytes, HASH_CHAIN_BUFFER_SIZE)],
+            data,
             #[cfg(debug_assertions)]
             last_index: 0,
         }
@@ -102,6 +105,13 @@ impl<const N: usize> MultiLevelHashChain<N> {
     }
 }
 
+/// Allocations of a [MultiLevelPrefixSearcher] that can be reused
+#[derive(Default)]
+pub(crate) struct MultiLevelTables<const N: usize> {
+    head: Option<PrefixTable>,
+    prev: Vec<HashChainLink<N>>,
+}
+
 /// This is an advanced version of the HashChain prefix searcher
 ///
 /// In the case of N=1 this is essentially equivalent to a standard hash chain;
@@ -338,17 +348,38 @@ impl<const N: usize> MultiLevelPrefixSearcher<'_, N> {
 }
 
 impl<'a, const N: usize> PrefixSearcher<'a> for MultiLevelPrefixSearcher<'a, N> {
-    fn build(buffer: &'a [u8]) -> Self {
-        let mut head = PrefixTable::new(buffer.len());
+    type Tables = MultiLevelTables<N>;
+
+    fn build(buffer: &'a [u8], tables: Self::Tables) -> Self {
+        let mut head = match tables.head {
+            Some(head) => head.reuse(buffer.len()),
+            None => PrefixTable::new(buffer.len()),
+        };
 
         head.insert(prefix_search::prefix(buffer), 0);
 
-        let prev = MultiLevelHashChain::new(buffer.len());
+        let prev = MultiLevelHashChain::new(tables.prev, buffer.len());
 
         Self { buffer, head, prev }
     }
 
+    fn into_tables(self) -> Self::Tables {
+        MultiLevelTables {
+            head: Some(self.head),
+            prev: self.prev.data,
+        }
+    }
+
     fn search<F: FnMut(usize, usize, usize)>(&mut self, search_position: usize, mut found_fn: F) {
+        // the end of a skip chain can lie past the maximum offset, matches found from there on
+        // are only further away, and would otherwise depend on how much of the input came before
+        let long_offset_limit = search_position.saturating_sub(LONG_OFFSET_MAX as usize);
+        let mut found_fn = |position, start, end| {
+            if position >= long_offset_limit {
+                found_fn(position, start, end);
+            }
+        };
+
         let cur_prefix = prefix_search::prefix(&self.buffer[search_position..]);
 
         // reset the current link in the hash chain
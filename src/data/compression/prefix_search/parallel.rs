@@ -0,0 +1,330 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Searching for matches on worker threads ahead of the optimal encoder
+//!
+//! The matches a [PrefixSearcher] reports at a position only depend on the
+//! bytes from [LONG_OFFSET_MAX] before that position onward, since it reports
+//! the closest match of every length within that distance. This means the input
+//! can be split into chunks that are searched independently, each by its own
+//! searcher that first goes over the [LONG_OFFSET_MAX] bytes before the chunk
+//! without reporting anything. Handing the matches of every chunk to the
+//! encoder in order then gives it exactly the matches a single searcher would
+//! have found.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Mutex, PoisonError};
+use std::{mem, thread};
+
+use crate::data::compression::prefix_search::{MatchFinder, PrefixSearcher};
+use crate::data::control::LONG_OFFSET_MAX;
+
+/// Amount of positions searched by a worker at a time. Every chunk also has to
+/// search through the [LONG_OFFSET_MAX] positions before it, so this keeps that
+/// overhead small while keeping the memory of the matches found ahead bounded.
+pub(crate) const CHUNK_LENGTH: usize = 1 << 20;
+
+#[derive(Copy, Clone)]
+struct FoundMatch {
+    position: u32,
+    start: u16,
+    end: u16,
+}
+
+/// Matches found in a single chunk
+#[derive(Default)]
+struct Chunk {
+    /// number of matches found at every position of the chunk
+    counts: Vec<u16>,
+    /// matches of all positions one after another
+    matches: Vec<FoundMatch>,
+}
+
+/// The positions to search in chunk `index` of an input of `length` bytes.
+/// Like the encoder, this skips the first position and the last three.
+fn chunk_range(index: usize, chunk_length: usize, length: usize) -> Range<usize> {
+    (index * chunk_length).max(1)..((index + 1) * chunk_length).min(length - 2)
+}
+
+/// Search all positions in `range` into `chunk`, reusing the allocations of
+/// `tables` and giving them back afterwards
+fn search_chunk<'a, PS: PrefixSearcher<'a>>(
+    input: &'a [u8],
+    range: Range<usize>,
+    tables: PS::Tables,
+    chunk: &mut Chunk,
+) -> PS::Tables {
+    let history_start = range.start.saturating_sub(LONG_OFFSET_MAX as usize);
+    let mut searcher = PS::build(&input[history_start..], tables);
+
+    for pos in 1..range.start - history_start {
+        searcher.search(pos, |_, _, _| {});
+    }
+
+    chunk.counts.clear();
+    chunk.matches.clear();
+    for pos in range {
+        let found = chunk.matches.len();
+        searcher.search(pos - history_start, |position, start, end| {
+            chunk.matches.push(FoundMatch {
+                position: (history_start + position) as u32,
+                start: start as u16,
+                end: end as u16,
+            });
+        });
+        chunk.counts.push((chunk.matches.len() - found) as u16);
+    }
+
+    searcher.into_tables()
+}
+
+/// Matches searched for by worker threads, to be consumed in order by the
+/// encoder
+pub(crate) struct ParallelMatches {
+    chunk_length: usize,
+    length: usize,
+    chunks: usize,
+    /// how many chunks are allowed to be searched ahead of the current chunk
+    ahead: usize,
+    /// index of the next chunk to be consumed
+    next: usize,
+    current: Chunk,
+    current_range: Range<usize>,
+    /// index in the current chunk of the matches of the next position
+    match_index: usize,
+    /// chunks that were searched before the chunks in front of them
+    received: HashMap<usize, Chunk>,
+    /// chunk indices to be searched, along with a chunk to reuse the allocations of
+    tickets: Sender<(usize, Chunk)>,
+    results: Receiver<thread::Result<(usize, Chunk)>>,
+}
+
+impl ParallelMatches {
+    /// Move on to the next chunk, handing the current one back to the workers
+    /// to search a chunk further ahead
+    fn advance(&mut self) {
+        let finished = mem::take(&mut self.current);
+        // finishing a chunk makes room for another one to be searched ahead
+        let ticket = self.next + self.ahead - 1;
+        if self.next > 0 && ticket < self.chunks {
+            // workers only stop once the tickets are dropped
+            let _ = self.tickets.send((ticket, finished));
+        }
+
+        self.current = loop {
+            if let Some(chunk) = self.received.remove(&self.next) {
+                break chunk;
+            }
+            match self.results.recv() {
+                Ok(Ok((index, chunk))) => {
+                    self.received.insert(index, chunk);
+                }
+                Ok(Err(panic)) => resume_unwind(panic),
+                Err(_) => unreachable!("workers only stop once the tickets are dropped"),
+            }
+        };
+        self.current_range = chunk_range(self.next, self.chunk_length, self.length);
+        self.match_index = 0;
+        self.next += 1;
+    }
+}
+
+impl MatchFinder for ParallelMatches {
+    fn find<F: FnMut(usize, usize, usize)>(&mut self, pos: usize, mut found_fn: F) {
+        while pos >= self.current_range.end {
+            self.advance();
+        }
+
+        let count = self.current.counts[pos - self.current_range.start] as usize;
+        for found in &self.current.matches[self.match_index..self.match_index + count] {
+            found_fn(
+                found.position as usize,
+                found.start as usize,
+                found.end as usize,
+            );
+        }
+        self.match_index += count;
+    }
+}
+
+/// Search `input` with searchers of type `PS` on `workers` threads, while
+/// `consume` is given the found matches on the current thread.
+///
+/// `consume` has to find the matches of every position of the input in order,
+/// skipping the first position and the last three.
+pub(crate) fn with_parallel_matches<'a, PS: PrefixSearcher<'a>, T>(
+    input: &'a [u8],
+    chunk_length: usize,
+    workers: usize,
+    consume: impl FnOnce(&mut ParallelMatches) -> T,
+) -> T {
+    let chunks = (input.len() - 2).div_ceil(chunk_length);
+    let workers = workers.clamp(1, chunks);
+
+    let (tickets, ticket_receiver) = channel::<(usize, Chunk)>();
+    let ticket_receiver = Mutex::new(ticket_receiver);
+    let (result_sender, results) = channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let ticket_receiver = &ticket_receiver;
+            let result_sender = result_sender.clone();
+            scope.spawn(move || {
+                let mut tables = PS::Tables::default();
+                loop {
+                    let ticket = ticket_receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv();
+                    let Ok((index, mut chunk)) = ticket else {
+                        break;
+                    };
+
+                    // hand any panic to the encoder, which would otherwise wait for this chunk
+                    // forever
+                    let result = catch_unwind(AssertUnwindSafe(|| {
+                        let range = chunk_range(index, chunk_length, input.len());
+                        tables =
+                            search_chunk::<PS>(input, range, mem::take(&mut tables), &mut chunk);
+                    }));
+                    let failed = result.is_err();
+                    let _ = result_sender.send(result.map(|()| (index, chunk)));
+                    if failed {
+                        break;
+                    }
+                }
+            });
+        }
+
+        // every worker gets a chunk to start with, and one more is searched ahead
+        let ahead = workers + 1;
+        for index in 0..ahead.min(chunks) {
+            let _ = tickets.send((index, Chunk::default()));
+        }
+
+        let mut matches = ParallelMatches {
+            chunk_length,
+            length: input.len(),
+            chunks,
+            ahead,
+            next: 0,
+            current: Chunk::default(),
+            current_range: 0..0,
+            match_index: 0,
+            received: HashMap::new(),
+            tickets,
+            results,
+        };
+
+        // dropping the matches drops the tickets, which stops the workers
+        consume(&mut matches)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::prefix_search::multi_level_hash_chain::{
+        MultiLevelPrefixSearcher,
+        MultiLevelTables,
+    };
+
+    type Searcher<'a> = MultiLevelPrefixSearcher<'a, 4>;
+
+    fn sequential_matches(input: &[u8]) -> Vec<(usize, usize, usize, usize)> {
+        let mut searcher = Searcher::build(input, MultiLevelTables::default());
+        let mut found = vec![];
+        for pos in 1..input.len() - 2 {
+            searcher.search(pos, |position, start, end| {
+                found.push((pos, position, start, end));
+            });
+        }
+        found
+    }
+
+    fn parallel_matches(
+        input: &[u8],
+        chunk_length: usize,
+        workers: usize,
+    ) -> Vec<(usize, usize, usize, usize)> {
+        with_parallel_matches::<Searcher, _>(input, chunk_length, workers, |matches| {
+            let mut found = vec![];
+            for pos in 1..input.len() - 2 {
+                matches.find(pos, |position, start, end| {
+                    found.push((pos, position, start, end));
+                });
+            }
+            found
+        })
+    }
+
+    #[proptest]
+    fn matches_sequential_search(
+        #[strategy(proptest::collection::vec(0..=3u8, 4..=2_000))] input: Vec<u8>,
+        #[strategy(50..=500usize)] chunk_length: usize,
+        #[strategy(1..=4usize)] workers: usize,
+    ) {
+        prop_assert_eq!(
+            parallel_matches(&input, chunk_length, workers),
+            sequential_matches(&input)
+        );
+    }
+
+    // every case searches a few hundred KiB in full, twice
+    #[proptest(ProptestConfig { cases: 8, ..ProptestConfig::default() })]
+    fn matches_sequential_search_with_truncated_history(
+        #[strategy(LONG_OFFSET_MAX as usize + 1_000..=LONG_OFFSET_MAX as usize * 2)] length: usize,
+        #[strategy(2..=16u64)] symbols: u64,
+        seed: u64,
+        // the distance of a repeat placed at the end of the input, around the edge of the history
+        // that chunks past the start of the input get to see
+        #[strategy(LONG_OFFSET_MAX as usize - 64..=LONG_OFFSET_MAX as usize + 64)] distance: usize,
+        #[strategy(1_000..=50_000usize)] chunk_length: usize,
+        #[strategy(1..=4usize)] workers: usize,
+    ) {
+        let mut state = seed | 1;
+        let mut input: Vec<u8> = (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state % symbols) as u8
+            })
+            .collect();
+        let repeat = length - distance - 100..length - distance;
+        input.copy_within(repeat, length - 100);
+
+        prop_assert!(parallel_matches(&input, chunk_length, workers) == sequential_matches(&input));
+    }
+
+    #[test]
+    fn matches_sequential_search_past_history() {
+        // chunks far enough into the input to not see the start of it,
+        // with matches that reach back all the way
+        let input: Vec<u8> = (0..(LONG_OFFSET_MAX as usize * 3) as u32)
+            .map(|i| ((i / 7) ^ (i % (LONG_OFFSET_MAX - 3))) as u8)
+            .collect();
+
+        assert!(parallel_matches(&input, 50_000, 3) == sequential_matches(&input));
+    }
+
+    #[test]
+    #[should_panic(expected = "searched past the end")]
+    fn forwards_consumer_panics() {
+        let input = vec![0; 1_000];
+        with_parallel_matches::<Searcher, _>(&input, 100, 2, |matches| {
+            matches.find(1, |_, _, _| {});
+            panic!("searched past the end");
+        });
+    }
+}
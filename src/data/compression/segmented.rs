@@ -0,0 +1,267 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Optimal compression of very large inputs split into segments
+//!
+//! Every segment is encoded by the optimal encoder on its own thread, with the
+//! [LONG_OFFSET_MAX] bytes before it available to copy from, so matches across
+//! segment boundaries are only lost for the copies that would cross one. The
+//! controls of all segments are then stitched together into a single stream:
+//! the stopcode of every segment but the last is dropped, and its literals are
+//! merged with the literals at the start of the next segment.
+
+use std::mem;
+
+use crate::RefPackResult;
+use crate::data::batch::{run_batch, thread_count};
+use crate::data::compression::optimal::{HASH_CHAINING_LEVELS, encode_slice_hc};
+use crate::data::compression::prefix_search::multi_level_hash_chain::MultiLevelPrefixSearcher;
+use crate::data::compression::{CompressionScratch, write_controls};
+use crate::data::control::{Command, CommandKind, Control, LITERAL_MAX, LONG_OFFSET_MAX};
+use crate::error::Error as RefPackError;
+use crate::format::Format;
+
+/// Inputs are never split into segments smaller than this, so the history that
+/// every segment has to search through stays small in comparison
+pub(crate) const MIN_SEGMENT_LENGTH: usize = 1 << 20;
+
+/// Upper bound of the amount of bytes that splitting the input at a single
+/// boundary can add to the output.
+///
+/// The copy crossing the boundary in the optimal encoding gets split in two,
+/// with either half being encoded as a copy of at most 4 bytes or as up to 4
+/// literals. The literal runs on both sides of the boundary get merged, which
+/// can take up to 2 more literal commands than encoding them as one run.
+const MAX_BOUNDARY_EXCESS: usize = 16;
+
+/// Information about the segments of [compress_segmented]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentedReport {
+    /// Number of segments the input was split into
+    pub segments: usize,
+    /// Upper bound of the amount of bytes the output is larger than the output
+    /// of [Optimal](crate::CompressionOptions::Optimal). `0` when the input
+    /// wasn't split, in which case the output is identical.
+    pub max_excess: usize,
+}
+
+/// Split the bytes of `input` from `start` onward into segments of at least `min_segment_length`
+/// bytes and encode them on up to `threads` threads. The bytes before `start` are only used as
+/// matches for copies.
+///
+/// Returns the stitched controls along with the amount of segments
+pub(crate) fn encode(
+    input: &[u8],
+    start: usize,
+    min_segment_length: usize,
+    threads: usize,
+) -> (Vec<Control>, usize) {
+    let length = input.len() - start;
+    let threads = thread_count(threads);
+    let segment_length = length.div_ceil(threads).max(min_segment_length);
+    let segments = length.div_ceil(segment_length);
+
+    let encoded = run_batch(
+        segments,
+        threads,
+        |scratch: &mut CompressionScratch, index| {
+            let start = start + index * segment_length;
+            let end = (start + segment_length).min(input.len());
+            let history_start = start.saturating_sub(LONG_OFFSET_MAX as usize);
+
+            encode_slice_hc::<MultiLevelPrefixSearcher<{ HASH_CHAINING_LEVELS }>>(
+                &input[history_start..end],
+                start - history_start,
+                scratch,
+            )
+        },
+    );
+
+    (stitch(encoded), segments)
+}
+
+/// Join the controls of consecutive segments into a single stream
+fn stitch(segments: Vec<Vec<Control>>) -> Vec<Control> {
+    let mut controls = Vec::with_capacity(segments.iter().map(Vec::len).sum());
+    // literals of the dropped stopcode of the previous segment, along with any literals after it
+    // that still have to be merged with them
+    let mut carried: Vec<u8> = vec![];
+
+    for control in segments.into_iter().flatten() {
+        if control.command.is_stop() {
+            carried.extend_from_slice(&control.bytes);
+        } else if carried.is_empty() {
+            controls.push(control);
+        } else {
+            carried.extend_from_slice(&control.bytes);
+            if control.command.kind != CommandKind::Literal {
+                push_literals(&mut controls, mem::take(&mut carried), control.command);
+            }
+        }
+    }
+
+    push_literals(&mut controls, carried, Command::new_stop(0));
+    controls
+}
+
+/// Push `literals` as literal blocks, with the remainder that doesn't fit
+/// into a block attached to `command`
+fn push_literals(controls: &mut Vec<Control>, mut literals: Vec<u8>, command: Command) {
+    let remainder = literals.split_off(literals.len() - literals.len() % 4);
+
+    for block in literals.chunks(LITERAL_MAX as usize) {
+        controls.push(Control::new_literal_block(block));
+    }
+
+    controls.push(Control::new(
+        Command {
+            literal: remainder.len() as u8,
+            ..command
+        },
+        remainder,
+    ));
+}
+
+/// Compress `input` with the [OptimalSegmented](crate::CompressionOptions::OptimalSegmented)
+/// algorithm on up to `threads` threads, reporting how much larger the output may be than the
+/// output of [Optimal](crate::CompressionOptions::Optimal).
+///
+/// The input is split into as many segments as there are threads, but never into segments smaller
+/// than 1 MiB. Every segment is compressed optimally with the 128 KiB before it available to copy
+/// from, so the output only differs from [Optimal](crate::CompressionOptions::Optimal) around the
+/// boundaries between segments, by at most 16 bytes per boundary.
+///
+/// A `threads` of `0` uses as many threads as
+/// [available_parallelism](std::thread::available_parallelism) reports.
+///
+/// # Example
+///
+/// ```
+/// use refpack::compress_segmented;
+/// use refpack::format::Reference;
+///
+/// let input = b"Hello World!".repeat(1000);
+///
+/// let (compressed, report) = compress_segmented::<Reference>(&input, 0).unwrap();
+///
+/// // too small to be split
+/// assert_eq!(report.segments, 1);
+/// assert_eq!(report.max_excess, 0);
+/// ```
+///
+/// # Errors
+/// - [RefPackError::EmptyInput]: Input is empty
+/// - [RefPackError::InputTooLarge]: Input or compressed data is too large for the header format
+/// - [RefPackError::Io]: Generic IO error when writing
+pub fn compress_segmented<F: Format>(
+    input: &[u8],
+    threads: usize,
+) -> RefPackResult<(Vec<u8>, SegmentedReport)> {
+    if input.is_empty() {
+        return Err(RefPackError::EmptyInput);
+    }
+
+    let (controls, segments) = encode(input, 0, MIN_SEGMENT_LENGTH, threads);
+    let compressed = write_controls::<F>(input.len(), &controls)?;
+
+    Ok((
+        compressed,
+        SegmentedReport {
+            segments,
+            max_excess: (segments - 1) * MAX_BOUNDARY_EXCESS,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::format::Reference;
+    use crate::{CompressionOptions, easy_compress, easy_decompress};
+
+    fn compressed_length(controls: &[Control]) -> usize {
+        write_controls::<Reference>(1, controls).unwrap().len()
+    }
+
+    #[proptest]
+    fn within_bound_of_optimal(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=2_000))] input: Vec<u8>,
+        #[strategy(1..=300usize)] segment_length: usize,
+        #[strategy(1..=4usize)] threads: usize,
+    ) {
+        let (controls, segments) = encode(&input, 0, segment_length, threads);
+        let written = write_controls::<Reference>(input.len(), &controls)?;
+        prop_assert_eq!(&easy_decompress::<Reference>(&written)?, &input);
+
+        let optimal = encode_slice_hc::<MultiLevelPrefixSearcher<{ HASH_CHAINING_LEVELS }>>(
+            &input,
+            0,
+            &mut CompressionScratch::default(),
+        );
+        let excess = compressed_length(&controls).saturating_sub(compressed_length(&optimal));
+        prop_assert!(excess <= (segments - 1) * MAX_BOUNDARY_EXCESS);
+    }
+
+    #[test]
+    fn copies_across_segments() {
+        // a long pattern that only repeats once, right across a boundary
+        let pattern: Vec<u8> = (0..5_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let input = [pattern.as_slice(), &pattern].concat();
+
+        let (controls, segments) = encode(&input, 0, 4_096, 4);
+        assert_eq!(segments, 3);
+
+        let written = write_controls::<Reference>(input.len(), &controls).unwrap();
+        assert_eq!(easy_decompress::<Reference>(&written).unwrap(), input);
+        // the second copy of the pattern only costs a handful of copy commands
+        assert!(written.len() < pattern.len() + 100);
+    }
+
+    #[test]
+    fn unsplit_matches_optimal() {
+        let input: Vec<u8> = (0..50_000u32)
+            .map(|i| ((i % 251) ^ (i / 97)) as u8)
+            .collect();
+
+        let (compressed, report) = compress_segmented::<Reference>(&input, 4).unwrap();
+
+        assert_eq!(report.segments, 1);
+        assert_eq!(report.max_excess, 0);
+        assert_eq!(
+            compressed,
+            easy_compress::<Reference>(&input, CompressionOptions::Optimal).unwrap()
+        );
+    }
+
+    #[test]
+    fn single_thread_does_not_split() {
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+
+        let (controls, segments) = encode(&input, 0, 4_096, 1);
+
+        assert_eq!(segments, 1);
+        assert_eq!(
+            controls,
+            encode_slice_hc::<MultiLevelPrefixSearcher<{ HASH_CHAINING_LEVELS }>>(
+                &input,
+                0,
+                &mut CompressionScratch::default(),
+            )
+        );
+    }
+
+    #[test]
+    fn empty_input_yields_error() {
+        assert!(matches!(
+            compress_segmented::<Reference>(&[], 0),
+            Err(RefPackError::EmptyInput)
+        ));
+    }
+}
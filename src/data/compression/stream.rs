@@ -0,0 +1,429 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Streaming compression through the [Write] trait
+//!
+//! Input is buffered in a sliding window that holds the last
+//! [LONG_OFFSET_MAX] bytes of input as match history, plus a block of input that
+//! has not been encoded yet. Controls are written to the output as soon as they
+//! are known.
+
+use std::cmp::{max, min};
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+
+use crate::data::compression::bytes_for_match;
+use crate::data::compression::fast::MAX_HASH_CHAIN_SEARCH_ITERATIONS;
+use crate::data::compression::match_length::match_length;
+use crate::data::compression::prefix_search::hash_chain::HashChain;
+use crate::data::compression::prefix_search::prefix;
+use crate::data::control::{
+    COPY_LITERAL_MAX,
+    Command,
+    LITERAL_MAX,
+    LONG_LENGTH_MAX,
+    LONG_OFFSET_MAX,
+    SHORT_OFFSET_MIN,
+};
+use crate::format::Format;
+use crate::header::mode::Mode as HeaderMode;
+use crate::header::{Header, check_length};
+use crate::{RefPackError, RefPackResult};
+
+/// Amount of history that always has to be kept around for matches
+const WINDOW_SIZE: usize = LONG_OFFSET_MAX as usize;
+
+/// Amount of input that gets buffered before it is encoded, and the amount of
+/// encoded input that is allowed to pile up past the window before it is
+/// discarded.
+const BLOCK_SIZE: usize = 1 << 16;
+
+/// Amount of input past a position that needs to be available before the
+/// position can be encoded without cutting matches short
+const LOOKAHEAD: usize = LONG_LENGTH_MAX as usize + 4;
+
+/// Compresses data written to it into `refpack` data in format `F`.
+///
+/// Matches are found the same way as [Fast](super::CompressionOptions::Fast)
+/// does, and the output is identical to compressing the entire input with it.
+/// Only the last [LONG_OFFSET_MAX] bytes of input are kept around, so the input
+/// does not need to fit in memory.
+///
+/// The header is written once [finish](Self::finish) is called, which seeks
+/// back to the start of the output. Dropping the encoder without finishing it
+/// leaves the output incomplete.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{Cursor, Write};
+///
+/// use refpack::format::Reference;
+/// use refpack::{RefPackEncoder, easy_decompress};
+///
+/// let mut encoder = RefPackEncoder::<_, Reference>::new(Cursor::new(vec![])).unwrap();
+/// encoder.write_all(b"Hello ").unwrap();
+/// encoder.write_all(b"World!").unwrap();
+/// let compressed = encoder.finish().unwrap().into_inner();
+///
+/// assert_eq!(
+///     easy_decompress::<Reference>(&compressed).unwrap(),
+///     b"Hello World!"
+/// );
+/// ```
+pub struct RefPackEncoder<W: Write + Seek, F: Format> {
+    writer: W,
+    header_position: u64,
+    header_length: usize,
+    data_start_position: u64,
+    /// match history followed by input that has not been encoded yet
+    buffer: Vec<u8>,
+    /// number of input bytes that have been discarded from the front of the
+    /// buffer
+    discarded: usize,
+    /// position in the buffer of the first byte that has not been encoded
+    cursor: usize,
+    literal_block: Vec<u8>,
+    prefix_table: HashChain,
+    _format: PhantomData<F>,
+}
+
+impl<W: Write + Seek, F: Format> RefPackEncoder<W, F> {
+    /// Create a new encoder writing to `writer`, starting at its current
+    /// position.
+    ///
+    /// Space for the header is reserved immediately. Some header formats need
+    /// more space for larger inputs, use [with_size_hint](Self::with_size_hint)
+    /// if the input may be larger than 16 MiB.
+    ///
+    /// # Errors
+    /// - [RefPackError::Io]: Generic IO error when seeking the writer
+    pub fn new(writer: W) -> RefPackResult<Self> {
+        Self::with_size_hint(writer, 0)
+    }
+
+    /// Create a new encoder writing to `writer`, reserving space for a header
+    /// that is able to describe `size` bytes of input.
+    ///
+    /// # Errors
+    /// - [RefPackError::InputTooLarge]: `size` is too large for the header
+    ///   format
+    /// - [RefPackError::Io]: Generic IO error when seeking the writer
+    pub fn with_size_hint(mut writer: W, size: usize) -> RefPackResult<Self> {
+        check_length(F::HeaderMode::MAX_DECOMPRESSED_LENGTH, size)?;
+        let header_length = F::HeaderMode::length(size);
+        let header_position = writer.stream_position()?;
+        let data_start_position = writer.seek(SeekFrom::Current(header_length as i64))?;
+
+        Ok(Self {
+            writer,
+            header_position,
+            header_length,
+            data_start_position,
+            buffer: Vec::with_capacity(WINDOW_SIZE + 2 * BLOCK_SIZE + LOOKAHEAD),
+            discarded: 0,
+            cursor: 0,
+            literal_block: Vec::with_capacity(LITERAL_MAX as usize),
+            prefix_table: HashChain::new(usize::MAX),
+            _format: PhantomData,
+        })
+    }
+
+    /// Total number of input bytes written to the encoder
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.discarded + self.buffer.len()
+    }
+
+    /// Gets a reference to the underlying writer
+    #[must_use]
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Encodes all remaining input, writes the stopcode and header, and
+    /// returns the underlying writer positioned at the end of the compressed
+    /// data.
+    ///
+    /// # Errors
+    /// - [RefPackError::EmptyInput]: No input was written to the encoder
+    /// - [RefPackError::InputTooLarge]: Input or compressed data is too large
+    ///   for the header format
+    /// - [RefPackError::Io]: Generic IO error when writing, or the input grew
+    ///   too large for the header space that was reserved
+    pub fn finish(mut self) -> RefPackResult<W> {
+        let length = self.position();
+        if length == 0 {
+            return Err(RefPackError::EmptyInput);
+        }
+        check_length(F::HeaderMode::MAX_DECOMPRESSED_LENGTH, length)?;
+        if F::HeaderMode::length(length) != self.header_length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Input of {length} bytes does not fit in the reserved header, use \
+                     `with_size_hint` to reserve a larger header"
+                ),
+            )
+            .into());
+        }
+
+        self.encode_buffered(true)?;
+
+        // Add remaining literals if there are any
+        let cursor = self.cursor;
+        self.literal_block.extend_from_slice(&self.buffer[cursor..]);
+        // Extremely similar to literal blocks before copies, but with a stopcode
+        let split_point = if self.literal_block.len() > COPY_LITERAL_MAX as usize {
+            let split_point = self.literal_block.len() - (self.literal_block.len() % 4);
+            Command::new_literal(split_point as u8).write(&mut self.writer)?;
+            self.writer.write_all(&self.literal_block[..split_point])?;
+            split_point
+        } else {
+            0
+        };
+        let stop_literals = &self.literal_block[split_point..];
+        Command::new_stop(stop_literals.len()).write(&mut self.writer)?;
+        self.writer.write_all(stop_literals)?;
+
+        let data_end_position = self.writer.stream_position()?;
+
+        let header = Header::with_lengths::<F::HeaderMode>(
+            length,
+            (data_end_position - self.data_start_position) as usize,
+        )?;
+
+        self.writer.seek(SeekFrom::Start(self.header_position))?;
+        header.write::<F::HeaderMode>(&mut self.writer)?;
+        self.writer.seek(SeekFrom::Start(data_end_position))?;
+
+        Ok(self.writer)
+    }
+
+    /// Write out the literal block ahead of a copy command
+    fn write_copy(&mut self, distance: usize, length: usize) -> RefPackResult<()> {
+        // If the current literal block is longer than the copy limit we need to split the block
+        let split_point = if self.literal_block.len() > COPY_LITERAL_MAX as usize {
+            let split_point = self.literal_block.len() - (self.literal_block.len() % 4);
+            Command::new_literal(split_point as u8).write(&mut self.writer)?;
+            self.writer.write_all(&self.literal_block[..split_point])?;
+            split_point
+        } else {
+            0
+        };
+        let copy_literals = &self.literal_block[split_point..];
+        Command::new(distance as u32, length as u16, copy_literals.len() as u8)
+            .write(&mut self.writer)?;
+        self.writer.write_all(copy_literals)?;
+        self.literal_block.clear();
+        Ok(())
+    }
+
+    /// Encode all buffered input that has enough input after it to find full
+    /// length matches, or all of it when `finish` is set.
+    fn encode_buffered(&mut self, finish: bool) -> RefPackResult<()> {
+        let buffer_length = self.buffer.len();
+        // positions that still have a full prefix after them
+        let prefix_end = max(3, buffer_length) - 3;
+        let end = if finish {
+            prefix_end
+        } else {
+            buffer_length.saturating_sub(LOOKAHEAD)
+        };
+
+        let mut i = self.cursor;
+        while i < end {
+            let key = prefix(&self.buffer[i..]);
+
+            // get the position of the prefix in the table (if it exists)
+            let matched = self.prefix_table.insert(key, (self.discarded + i) as u32);
+
+            let pair = matched
+                .take(MAX_HASH_CHAIN_SEARCH_ITERATIONS)
+                .filter_map(|matched| {
+                    // the history is always kept at least as long as the maximum offset, so any
+                    // position the hash chain returns is still in the buffer
+                    let matched = matched as usize - self.discarded;
+                    let distance = i - matched;
+                    if distance > LONG_OFFSET_MAX as usize || distance < SHORT_OFFSET_MIN as usize {
+                        None
+                    } else {
+                        // find the longest common prefix
+                        let max_copy_len = LONG_LENGTH_MAX as usize;
+                        let match_length = match_length(&self.buffer, i, matched, max_copy_len, 3);
+
+                        let num_bytes = bytes_for_match(match_length, distance)?.0?;
+                        Some((
+                            matched,
+                            match_length,
+                            match_length as f64 / num_bytes as f64,
+                        ))
+                    }
+                })
+                .max_by(|(_, _, r1), (_, _, r2)| r1.total_cmp(r2));
+
+            if let Some((found, match_length, _)) = pair {
+                self.write_copy(i - found, match_length)?;
+
+                for k in (i..).take(match_length).skip(1) {
+                    if k >= prefix_end {
+                        break;
+                    }
+                    let _ = self
+                        .prefix_table
+                        .insert(prefix(&self.buffer[k..]), (self.discarded + k) as u32);
+                }
+
+                i += match_length;
+            } else {
+                self.literal_block.push(self.buffer[i]);
+                i += 1;
+                // If it's reached the limit, write the block immediately and clear the running
+                // block
+                if self.literal_block.len() >= (LITERAL_MAX as usize) {
+                    Command::new_literal(LITERAL_MAX).write(&mut self.writer)?;
+                    self.writer.write_all(&self.literal_block)?;
+                    self.literal_block.clear();
+                }
+            }
+        }
+        self.cursor = i;
+
+        Ok(())
+    }
+
+    /// Drops input that has been encoded and is too far back to be matched
+    /// against anymore
+    fn discard_encoded(&mut self) {
+        if self.cursor > WINDOW_SIZE + BLOCK_SIZE {
+            let discard = self.cursor - WINDOW_SIZE;
+            self.buffer.copy_within(discard.., 0);
+            self.buffer.truncate(self.buffer.len() - discard);
+            self.cursor -= discard;
+            self.discarded += discard;
+        }
+    }
+}
+
+impl<W: Write + Seek, F: Format> Write for RefPackEncoder<W, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // positions in the hash chain are stored as u32
+        if self.position() + buf.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Input is too large to be encoded",
+            ));
+        }
+
+        let amount = min(buf.len(), BLOCK_SIZE);
+        self.buffer.extend_from_slice(&buf[..amount]);
+
+        if self.buffer.len() >= self.cursor + BLOCK_SIZE + LOOKAHEAD {
+            self.encode_buffered(false)?;
+            self.discard_encoded();
+        }
+
+        Ok(amount)
+    }
+
+    /// Flushes the underlying writer. This does not force buffered input to be
+    /// encoded, as that would require ending the stream.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::CompressionOptions;
+    use crate::format::{Maxis, Reference, SimEA};
+    use crate::{easy_compress, easy_decompress};
+
+    fn stream_compress<F: Format>(input: &[u8], chunk_size: usize) -> RefPackResult<Vec<u8>> {
+        let mut encoder = RefPackEncoder::<_, F>::new(Cursor::new(vec![]))?;
+        for chunk in input.chunks(chunk_size) {
+            encoder.write_all(chunk)?;
+        }
+        Ok(encoder.finish()?.into_inner())
+    }
+
+    #[proptest]
+    fn matches_fast_compression(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=10_000))] input: Vec<u8>,
+        #[strategy(1..=4096usize)] chunk_size: usize,
+    ) {
+        let expected = easy_compress::<Maxis>(&input, CompressionOptions::Fast)?;
+        let got = stream_compress::<Maxis>(&input, chunk_size)?;
+
+        prop_assert_eq!(got, expected);
+    }
+
+    #[proptest]
+    fn symmetrical_stream_compression(
+        #[strategy(proptest::collection::vec(any::<u8>(), 1..=2_000))] input: Vec<u8>,
+        #[strategy(1..=256usize)] chunk_size: usize,
+    ) {
+        let compressed = stream_compress::<SimEA>(&input, chunk_size)?;
+        let got = easy_decompress::<SimEA>(&compressed)?;
+
+        prop_assert_eq!(got, input);
+    }
+
+    #[test]
+    fn compresses_past_window() {
+        let input: Vec<u8> = (0..(WINDOW_SIZE * 6) as u32)
+            .map(|i| ((i / 7) ^ (i % (WINDOW_SIZE as u32 - 3))) as u8)
+            .collect();
+
+        let compressed = stream_compress::<Reference>(&input, 10_000).unwrap();
+
+        assert!(
+            compressed == easy_compress::<Reference>(&input, CompressionOptions::Fast).unwrap()
+        );
+        assert!(easy_decompress::<Reference>(&compressed).unwrap() == input);
+    }
+
+    #[test]
+    fn writes_after_existing_data() {
+        let mut writer = Cursor::new(b"prefix".to_vec());
+        writer.set_position(6);
+        let mut encoder = RefPackEncoder::<_, Maxis>::new(writer).unwrap();
+        encoder.write_all(b"Hello World!").unwrap();
+        let out = encoder.finish().unwrap().into_inner();
+
+        assert_eq!(&out[..6], b"prefix");
+        assert_eq!(
+            easy_decompress::<Maxis>(&out[6..]).unwrap(),
+            b"Hello World!"
+        );
+    }
+
+    #[test]
+    fn empty_input_yields_error() {
+        let encoder = RefPackEncoder::<_, Reference>::new(Cursor::new(vec![])).unwrap();
+        assert!(matches!(encoder.finish(), Err(RefPackError::EmptyInput)));
+    }
+
+    #[test]
+    fn too_large_size_hint_yields_error() {
+        let result = RefPackEncoder::<_, Maxis>::with_size_hint(Cursor::new(vec![]), 0x1_00_00_00);
+        assert!(matches!(
+            result,
+            Err(RefPackError::InputTooLarge {
+                max: 0xFF_FF_FF,
+                ..
+            })
+        ));
+    }
+}
@@ -5,17 +5,17 @@
 //                                                                             /
 ////////////////////////////////////////////////////////////////////////////////
 
-use std::io::{Read, Seek};
+use std::io::Read;
 
 use crate::data::control::Control;
 
 /// Iterator to to read a byte reader into a sequence of controls
-pub struct Iter<'a, R: Read + Seek> {
+pub struct Iter<'a, R: Read> {
     reader: &'a mut R,
     reached_stop: bool,
 }
 
-impl<'a, R: Read + Seek> Iter<'a, R> {
+impl<'a, R: Read> Iter<'a, R> {
     pub fn new(reader: &'a mut R) -> Iter<'a, R> {
         Iter::<'a, R> {
             reader,
@@ -24,7 +24,7 @@ impl<'a, R: Read + Seek> Iter<'a, R> {
     }
 }
 
-impl<'a, R: Read + Seek> Iterator for Iter<'a, R> {
+impl<'a, R: Read> Iterator for Iter<'a, R> {
     type Item = Control;
 
     fn next(&mut self) -> Option<Self::Item> {
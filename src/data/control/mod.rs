@@ -10,7 +10,7 @@
 #[cfg(test)]
 mod iterator;
 
-use std::io::{Read, Seek, Write};
+use std::io::{ErrorKind, Read, Seek, Write};
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 #[cfg(test)]
@@ -309,20 +309,25 @@ impl Command {
     /// # Errors
     /// - [RefPackError::Io]: Failed to get remaining single byte from reader
     #[inline(always)]
-    pub fn read_short(first: u8, reader: &mut (impl Read + Seek)) -> RefPackResult<Self> {
+    pub fn read_short(first: u8, reader: &mut impl Read) -> RefPackResult<Self> {
+        Ok(Self::decode_short(first, reader.read_u8()?))
+    }
+
+    #[inline(always)]
+    fn decode_short(first: u8, second: u8) -> Self {
         let byte1 = first as usize;
-        let byte2: usize = reader.read_u8()?.into();
+        let byte2 = second as usize;
 
         let offset = ((((byte1 & 0b0110_0000) << 3) | byte2) + 1) as u32;
         let length = (((byte1 & 0b0001_1100) >> 2) + 3) as u16;
         let literal = (byte1 & 0b0000_0011) as u8;
 
-        Ok(Self {
+        Self {
             offset,
             length,
             literal,
             kind: CommandKind::Short,
-        })
+        }
     }
 
     /// Reference read implementation of medium copy commands. See struct
@@ -331,21 +336,28 @@ impl Command {
     /// # Errors
     /// - [RefPackError::Io]: Failed to get remaining two bytes from reader
     #[inline(always)]
-    pub fn read_medium(first: u8, reader: &mut (impl Read + Seek)) -> RefPackResult<Self> {
-        let byte1: usize = first as usize;
-        let byte2: usize = reader.read_u8()?.into();
-        let byte3: usize = reader.read_u8()?.into();
+    pub fn read_medium(first: u8, reader: &mut impl Read) -> RefPackResult<Self> {
+        let mut rest = [0; 2];
+        reader.read_exact(&mut rest)?;
+        Ok(Self::decode_medium(first, rest))
+    }
+
+    #[inline(always)]
+    fn decode_medium(first: u8, [second, third]: [u8; 2]) -> Self {
+        let byte1 = first as usize;
+        let byte2 = second as usize;
+        let byte3 = third as usize;
 
         let offset = ((((byte2 & 0b0011_1111) << 8) | byte3) + 1) as u32;
         let length = ((byte1 & 0b0011_1111) + 4) as u16;
         let literal = ((byte2 & 0b1100_0000) >> 6) as u8;
 
-        Ok(Self {
+        Self {
             offset,
             length,
             literal,
             kind: CommandKind::Medium,
-        })
+        }
     }
 
     /// Reference read implementation of long commands. See struct definition
@@ -354,23 +366,30 @@ impl Command {
     /// # Errors
     /// - [RefPackError::Io]: Failed to get remaining three bytes from the reader
     #[inline(always)]
-    pub fn read_long(first: u8, reader: &mut (impl Read + Seek)) -> RefPackResult<Self> {
-        let byte1: usize = first as usize;
-        let byte2: usize = reader.read_u8()?.into();
-        let byte3: usize = reader.read_u8()?.into();
-        let byte4: usize = reader.read_u8()?.into();
+    pub fn read_long(first: u8, reader: &mut impl Read) -> RefPackResult<Self> {
+        let mut rest = [0; 3];
+        reader.read_exact(&mut rest)?;
+        Ok(Self::decode_long(first, rest))
+    }
+
+    #[inline(always)]
+    fn decode_long(first: u8, [second, third, fourth]: [u8; 3]) -> Self {
+        let byte1 = first as usize;
+        let byte2 = second as usize;
+        let byte3 = third as usize;
+        let byte4 = fourth as usize;
 
         let offset = ((((byte1 & 0b0001_0000) << 12) | (byte2 << 8) | byte3) + 1) as u32;
         let length = ((((byte1 & 0b0000_1100) << 6) | byte4) + 5) as u16;
 
         let literal = (byte1 & 0b0000_0011) as u8;
 
-        Ok(Self {
+        Self {
             offset,
             length,
             literal,
             kind: CommandKind::Long,
-        })
+        }
     }
 
     /// Reference read implementation of literal commands. See struct definition
@@ -394,12 +413,12 @@ impl Command {
         Self::new_stop_unchecked(first & 0b0000_0011)
     }
 
-    /// Reads and decodes a command from a `Read + Seek` reader.
+    /// Reads and decodes a command from a `Read` reader.
     /// # Errors
     /// - [RefPackError::Io]: Generic IO error occurred while attempting to read
     ///   data
     #[inline(always)]
-    pub fn read(reader: &mut (impl Read + Seek)) -> RefPackResult<Self> {
+    pub fn read(reader: &mut impl Read) -> RefPackResult<Self> {
         let first = reader.read_u8()?;
 
         match first {
@@ -411,6 +430,36 @@ impl Command {
         }
     }
 
+    /// Reads and decodes a command from `input` at `position`, advancing
+    /// `position` past the command.
+    ///
+    /// Equivalent to [read](Self::read), but indexes into the slice directly
+    /// instead of going through [Read] for every byte.
+    /// # Errors
+    /// - [RefPackError::Io]: `input` ended before the end of the command
+    #[inline(always)]
+    pub fn read_slice(input: &[u8], position: &mut usize) -> RefPackResult<Self> {
+        let eof = || RefPackError::Io(ErrorKind::UnexpectedEof.into());
+
+        let first = *input.get(*position).ok_or_else(eof)?;
+        let length = match first {
+            0x00..=0x7F => 2,
+            0x80..=0xBF => 3,
+            0xC0..=0xDF => 4,
+            0xE0..=0xFF => 1,
+        };
+        let bytes = input.get(*position..*position + length).ok_or_else(eof)?;
+        *position += length;
+
+        Ok(match first {
+            0x00..=0x7F => Self::decode_short(first, bytes[1]),
+            0x80..=0xBF => Self::decode_medium(first, [bytes[1], bytes[2]]),
+            0xC0..=0xDF => Self::decode_long(first, [bytes[1], bytes[2], bytes[3]]),
+            0xE0..=0xFB => Self::read_literal(first),
+            0xFC..=0xFF => Self::read_stop(first),
+        })
+    }
+
     /// Reference write implementation of short copy commands. See struct
     /// definition for specification
     ///
@@ -590,11 +639,11 @@ impl Control {
         }
     }
 
-    /// Reads and decodes a control block from a `Read + Seek` reader
+    /// Reads and decodes a control block from a `Read` reader
     /// # Errors
     /// - [RefPackError::Io]: Generic IO error occurred while attempting to read
     ///   data
-    pub fn read(reader: &mut (impl Read + Seek)) -> Result<Self, RefPackError> {
+    pub fn read(reader: &mut impl Read) -> Result<Self, RefPackError> {
         let command = Command::read(reader)?;
         let mut buf = vec![0u8; command.num_of_literal().unwrap_or(0)];
         reader.read_exact(&mut buf)?;
@@ -772,6 +821,27 @@ pub(crate) mod tests {
         prop_assert_eq!(out, expected);
     }
 
+    #[proptest]
+    fn read_slice_matches_read(
+        #[strategy(proptest::collection::vec(any::<u8>(), 0..=4))] input: Vec<u8>,
+    ) {
+        let mut position = 0;
+        let from_slice = Command::read_slice(&input, &mut position);
+        let mut buf = Cursor::new(&input);
+        let from_reader = Command::read(&mut buf);
+
+        match (from_slice, from_reader) {
+            (Ok(from_slice), Ok(from_reader)) => {
+                prop_assert_eq!(from_slice, from_reader);
+                prop_assert_eq!(position as u64, buf.position());
+            }
+            (Err(RefPackError::Io(_)), Err(RefPackError::Io(_))) => {}
+            (from_slice, from_reader) => {
+                prop_assert!(false, "{from_slice:?} != {from_reader:?}");
+            }
+        }
+    }
+
     #[test]
     #[should_panic]
     fn command_reject_new_stop_invalid() {
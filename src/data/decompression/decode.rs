@@ -0,0 +1,575 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! The decoding step shared by every way of decompressing
+//!
+//! Decoding a control is always the same: its literals are written to the
+//! output, followed by its copy. What differs between the decompression
+//! functions is where the controls come from, a [Source], and where the output
+//! goes, a [Sink]. The bounds checks and copy semantics live here once, so
+//! every decompression path behaves identically.
+
+use std::cmp::min;
+use std::io::{BufRead, ErrorKind, Read};
+
+use crate::data::control::{Command, LITERAL_MAX};
+use crate::data::{
+    CountingReader,
+    DecodeError,
+    SPLAT_WORD_SIZE,
+    copy_from_reader,
+    rle_decode_fixed,
+};
+use crate::{RefPackError, RefPackResult};
+
+/// Shortest copy to repeat words for. Below this, zeroing the space for the
+/// copy first costs more than doubling it with [Vec::extend_from_within].
+const SPLAT_MIN_LENGTH: usize = 32;
+
+/// Where controls and their literals are read from
+pub(crate) trait Source {
+    /// Read the next command
+    ///
+    /// # Errors
+    /// - [RefPackError::Io]: The input ended, or a generic IO error occurred
+    fn command(&mut self) -> RefPackResult<Command>;
+
+    /// Read the `length` literals following a command
+    ///
+    /// # Errors
+    /// - [RefPackError::Io]: The input ended, or a generic IO error occurred
+    fn literals(&mut self, length: usize) -> RefPackResult<&[u8]>;
+
+    /// Amount of input that has been read
+    fn consumed(&self) -> usize;
+
+    /// Whether there is no more input. This may have to read past the data.
+    ///
+    /// # Errors
+    /// - [RefPackError::Io]: Generic IO error occurred
+    fn is_at_end(&mut self) -> RefPackResult<bool>;
+}
+
+/// Where decoded output is written to
+pub(crate) trait Sink {
+    /// Position of the end of the output, as reported in errors
+    fn position(&self) -> usize;
+
+    /// Write literal bytes to the end of the output
+    ///
+    /// # Errors
+    /// - [DecodeError::BadLength]: The output has no room for the literals
+    fn literals(&mut self, literals: &[u8]) -> Result<(), DecodeError>;
+
+    /// Copy `length` bytes from `offset` bytes back to the end of the output
+    ///
+    /// If this errors nothing will have been written
+    ///
+    /// # Errors
+    /// - [DecodeError::BadOffset]: `offset` is 0
+    /// - [DecodeError::NegativePosition]: `offset` reaches back before the
+    ///   start of the output
+    /// - [DecodeError::BadLength]: The output has no room for the copy
+    fn copy(&mut self, offset: usize, length: usize) -> Result<(), DecodeError>;
+}
+
+/// Write `literals` to `sink`
+///
+/// # Errors
+/// - [RefPackError::ControlError]: The literals don't fit
+#[inline(always)]
+pub(crate) fn write_literals(sink: &mut impl Sink, literals: &[u8]) -> RefPackResult<()> {
+    let position = sink.position();
+    sink.literals(literals)
+        .map_err(|error| RefPackError::ControlError { error, position })
+}
+
+/// Write the copy of `command` to `sink`
+///
+/// # Errors
+/// - [RefPackError::ControlError]: The copy is invalid or doesn't fit
+#[inline(always)]
+pub(crate) fn write_copy(sink: &mut impl Sink, command: Command) -> RefPackResult<()> {
+    if let Some((offset, length)) = command.offset_copy() {
+        let position = sink.position();
+        sink.copy(offset, length)
+            .map_err(|error| RefPackError::ControlError { error, position })?;
+    }
+    Ok(())
+}
+
+/// Write `command` to `sink`, with `literals` being the literals that
+/// were read for it
+///
+/// # Errors
+/// - [RefPackError::ControlError]: The literals or copy are invalid or don't
+///   fit
+#[inline(always)]
+pub(crate) fn write_control(
+    sink: &mut impl Sink,
+    command: Command,
+    literals: &[u8],
+) -> RefPackResult<()> {
+    write_literals(sink, literals)?;
+    write_copy(sink, command)
+}
+
+/// Read a single control from `source` and write its output to `sink`
+///
+/// # Returns
+/// the command that was decoded, so the caller can stop at the stopcode
+///
+/// # Errors
+/// - [RefPackError::ControlError]: The control is invalid or doesn't fit
+/// - [RefPackError::Io]: The input ended, or a generic IO error occurred
+#[inline(always)]
+pub(crate) fn decode_control(
+    source: &mut impl Source,
+    sink: &mut impl Sink,
+) -> RefPackResult<Command> {
+    let command = source.command()?;
+    let literals = source.literals(command.literal as usize)?;
+    write_control(sink, command, literals)?;
+    Ok(command)
+}
+
+fn unexpected_eof() -> RefPackError {
+    RefPackError::Io(ErrorKind::UnexpectedEof.into())
+}
+
+/// Controls parsed straight from a slice
+pub(crate) struct SliceSource<'a> {
+    pub(crate) input: &'a [u8],
+    /// position of the next control in `input`
+    pub(crate) position: usize,
+}
+
+impl Source for SliceSource<'_> {
+    #[inline(always)]
+    fn command(&mut self) -> RefPackResult<Command> {
+        Command::read_slice(self.input, &mut self.position)
+    }
+
+    #[inline(always)]
+    fn literals(&mut self, length: usize) -> RefPackResult<&[u8]> {
+        let literals = self
+            .input
+            .get(self.position..self.position + length)
+            .ok_or_else(unexpected_eof)?;
+        self.position += length;
+        Ok(literals)
+    }
+
+    fn consumed(&self) -> usize {
+        self.position
+    }
+
+    fn is_at_end(&mut self) -> RefPackResult<bool> {
+        Ok(self.position >= self.input.len())
+    }
+}
+
+/// Controls read through a reader, with the literals of a control buffered
+pub(crate) struct ReaderSource<R> {
+    pub(crate) reader: CountingReader<R>,
+    literals: [u8; LITERAL_MAX as usize],
+}
+
+impl<R: Read> ReaderSource<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader: CountingReader::new(reader),
+            literals: [0; LITERAL_MAX as usize],
+        }
+    }
+
+    /// Unwraps the source, returning the underlying reader
+    pub(crate) fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+impl<R: Read> Source for ReaderSource<R> {
+    #[inline(always)]
+    fn command(&mut self) -> RefPackResult<Command> {
+        Command::read(&mut self.reader)
+    }
+
+    #[inline(always)]
+    fn literals(&mut self, length: usize) -> RefPackResult<&[u8]> {
+        // no command has more than `LITERAL_MAX` literals
+        copy_from_reader(&mut self.literals, &mut self.reader, 0, length)?;
+        Ok(&self.literals[..length])
+    }
+
+    fn consumed(&self) -> usize {
+        self.reader.count()
+    }
+
+    fn is_at_end(&mut self) -> RefPackResult<bool> {
+        Ok(self.reader.read(&mut [0])? == 0)
+    }
+}
+
+/// Longest a command can be, which is the length of a long copy command
+const COMMAND_LENGTH_MAX: usize = 4;
+
+/// Controls read through a buffered reader. Whenever the buffer of the reader
+/// holds an entire command or all literals of one, they are parsed straight
+/// from it like from a slice, which for an in-memory reader such as
+/// [Cursor](std::io::Cursor) is always the case.
+///
+/// Literals handed out from the buffer are only consumed from the reader once
+/// the source is used again or dropped.
+pub(crate) struct BufReadSource<R: BufRead> {
+    reader: R,
+    /// amount of input consumed from `reader`
+    count: usize,
+    /// length of the literals last handed out straight from the buffer of
+    /// `reader`, which are consumed before reading anything else
+    pending: usize,
+    literals: [u8; LITERAL_MAX as usize],
+}
+
+impl<R: BufRead> BufReadSource<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            count: 0,
+            pending: 0,
+            literals: [0; LITERAL_MAX as usize],
+        }
+    }
+
+    #[inline(always)]
+    fn consume_pending(&mut self) {
+        self.reader.consume(self.pending);
+        self.count += self.pending;
+        self.pending = 0;
+    }
+}
+
+impl<R: BufRead> Drop for BufReadSource<R> {
+    fn drop(&mut self) {
+        self.consume_pending();
+    }
+}
+
+impl<R: BufRead> Read for BufReadSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.consume_pending();
+        let read = self.reader.read(buf)?;
+        self.count += read;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> Source for BufReadSource<R> {
+    #[inline(always)]
+    fn command(&mut self) -> RefPackResult<Command> {
+        self.consume_pending();
+        let buffer = self.reader.fill_buf()?;
+        if buffer.len() < COMMAND_LENGTH_MAX {
+            // the command may continue past the buffer
+            let mut reader = CountingReader::new(&mut self.reader);
+            let command = Command::read(&mut reader);
+            self.count += reader.count();
+            return command;
+        }
+
+        let mut position = 0;
+        let command = Command::read_slice(buffer, &mut position)?;
+        self.reader.consume(position);
+        self.count += position;
+        Ok(command)
+    }
+
+    #[inline(always)]
+    fn literals(&mut self, length: usize) -> RefPackResult<&[u8]> {
+        self.consume_pending();
+        if self.reader.fill_buf()?.len() >= length {
+            self.pending = length;
+            return Ok(&self.reader.fill_buf()?[..length]);
+        }
+
+        // no command has more than `LITERAL_MAX` literals
+        copy_from_reader(&mut self.literals, &mut self.reader, 0, length)?;
+        self.count += length;
+        Ok(&self.literals[..length])
+    }
+
+    fn consumed(&self) -> usize {
+        self.count + self.pending
+    }
+
+    fn is_at_end(&mut self) -> RefPackResult<bool> {
+        self.consume_pending();
+        Ok(self.reader.fill_buf()?.is_empty())
+    }
+}
+
+/// Output appended to the end of a [Vec]
+pub(crate) struct VecSink<'a> {
+    pub(crate) buffer: &'a mut Vec<u8>,
+    /// start of the output in `buffer`, copies can't reach back before it
+    pub(crate) start: usize,
+    /// amount of output before `start` that is no longer in `buffer`, which is
+    /// added to positions in errors
+    pub(crate) discarded: usize,
+}
+
+impl<'a> VecSink<'a> {
+    /// Output appended to `buffer`, after its existing contents
+    pub(crate) fn new(buffer: &'a mut Vec<u8>) -> Self {
+        let start = buffer.len();
+        Self {
+            buffer,
+            start,
+            discarded: 0,
+        }
+    }
+
+    /// Amount of output that has been written
+    pub(crate) fn produced(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+}
+
+impl Sink for VecSink<'_> {
+    #[inline(always)]
+    fn position(&self) -> usize {
+        self.discarded + self.produced()
+    }
+
+    #[inline(always)]
+    fn literals(&mut self, literals: &[u8]) -> Result<(), DecodeError> {
+        self.buffer.extend_from_slice(literals);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn copy(&mut self, offset: usize, length: usize) -> Result<(), DecodeError> {
+        extend_from_offset(self.buffer, self.start, offset, length)
+    }
+}
+
+/// Output written to a fixed size buffer, erroring instead of growing it
+pub(crate) struct SliceSink<'a> {
+    pub(crate) out: &'a mut [u8],
+    /// amount of output written to the start of `out`
+    pub(crate) written: usize,
+}
+
+impl Sink for SliceSink<'_> {
+    #[inline(always)]
+    fn position(&self) -> usize {
+        self.written
+    }
+
+    #[inline(always)]
+    fn literals(&mut self, literals: &[u8]) -> Result<(), DecodeError> {
+        let end = self.written + literals.len();
+        let out_length = self.out.len();
+        self.out
+            .get_mut(self.written..end)
+            .ok_or(DecodeError::BadLength(end.saturating_sub(out_length)))?
+            .copy_from_slice(literals);
+        self.written = end;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn copy(&mut self, offset: usize, length: usize) -> Result<(), DecodeError> {
+        self.written = rle_decode_fixed(self.out, self.written, offset, length)?;
+        Ok(())
+    }
+}
+
+/// Output that is only counted, checking copies without producing anything
+#[derive(Default)]
+pub(crate) struct CountSink {
+    pub(crate) produced: usize,
+}
+
+impl Sink for CountSink {
+    #[inline(always)]
+    fn position(&self) -> usize {
+        self.produced
+    }
+
+    #[inline(always)]
+    fn literals(&mut self, literals: &[u8]) -> Result<(), DecodeError> {
+        self.produced += literals.len();
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn copy(&mut self, offset: usize, length: usize) -> Result<(), DecodeError> {
+        if offset == 0 {
+            return Err(DecodeError::BadOffset);
+        }
+        if offset > self.produced {
+            return Err(DecodeError::NegativePosition(self.produced, offset));
+        }
+        self.produced += length;
+        Ok(())
+    }
+}
+
+/// Output of `sink` cut off once `limit` bytes have been written, with the
+/// literals or copy crossing the limit cut short
+pub(crate) struct LimitedSink<S> {
+    pub(crate) sink: S,
+    pub(crate) limit: usize,
+}
+
+impl<S: Sink> LimitedSink<S> {
+    /// Amount of output that can still be written before the limit
+    pub(crate) fn remaining(&self) -> usize {
+        self.limit.saturating_sub(self.sink.position())
+    }
+}
+
+impl<S: Sink> Sink for LimitedSink<S> {
+    #[inline(always)]
+    fn position(&self) -> usize {
+        self.sink.position()
+    }
+
+    #[inline(always)]
+    fn literals(&mut self, literals: &[u8]) -> Result<(), DecodeError> {
+        let length = min(literals.len(), self.remaining());
+        self.sink.literals(&literals[..length])
+    }
+
+    #[inline(always)]
+    fn copy(&mut self, offset: usize, length: usize) -> Result<(), DecodeError> {
+        let length = min(length, self.remaining());
+        if length == 0 {
+            return Ok(());
+        }
+        self.sink.copy(offset, length)
+    }
+}
+
+/// Append `length` bytes copied from `offset` bytes back to the end of
+/// `buffer`, without reaching back before `start`
+///
+/// Copies that don't overlap and copies of a single byte are done directly on
+/// the end of the buffer. Long copies of a repeating word are done with
+/// [rle_decode_fixed], and anything else is doubled until it's long enough.
+#[inline(always)]
+pub(crate) fn extend_from_offset(
+    buffer: &mut Vec<u8>,
+    start: usize,
+    offset: usize,
+    length: usize,
+) -> Result<(), DecodeError> {
+    let produced = buffer.len() - start;
+    if offset == 0 {
+        return Err(DecodeError::BadOffset);
+    }
+    if offset > produced {
+        return Err(DecodeError::NegativePosition(produced, offset));
+    }
+
+    let copy_start = buffer.len() - offset;
+
+    if offset >= length {
+        buffer.extend_from_within(copy_start..copy_start + length);
+    } else if offset == 1 {
+        let byte = buffer[copy_start];
+        buffer.resize(buffer.len() + length, byte);
+    } else if SPLAT_WORD_SIZE % offset == 0 && length >= SPLAT_MIN_LENGTH {
+        let position = buffer.len();
+        buffer.resize(position + length, 0);
+        rle_decode_fixed(buffer, position, offset, length)?;
+    } else {
+        let mut remaining = length;
+        while remaining > 0 {
+            let amount = min(remaining, buffer.len() - copy_start);
+            buffer.extend_from_within(copy_start..copy_start + amount);
+            remaining -= amount;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+
+    #[proptest]
+    fn matches_fixed_decoding(
+        #[strategy(proptest::collection::vec(any::<u8>(), 1..=64))] history: Vec<u8>,
+        #[strategy(1..=#history.len())] offset: usize,
+        #[strategy(0..=300usize)] length: usize,
+    ) {
+        let mut want = history.clone();
+        want.resize(history.len() + length, 0);
+        rle_decode_fixed(&mut want, history.len(), offset, length)?;
+
+        let mut got = history.clone();
+        extend_from_offset(&mut got, 0, offset, length)?;
+
+        prop_assert_eq!(got, want);
+    }
+
+    #[test]
+    fn repeats_overlapping_copies() {
+        let mut buffer = vec![9, 1, 2, 3];
+        extend_from_offset(&mut buffer, 1, 3, 8).unwrap();
+
+        assert_eq!(buffer, [9, 1, 2, 3, 1, 2, 3, 1, 2, 3, 1, 2]);
+    }
+
+    #[test]
+    fn does_not_copy_before_start() {
+        let mut buffer = vec![9, 1, 2, 3];
+        let error = extend_from_offset(&mut buffer, 1, 4, 8).unwrap_err();
+
+        assert!(matches!(error, DecodeError::NegativePosition(3, 4)));
+    }
+
+    #[proptest]
+    fn sinks_agree(
+        #[strategy(proptest::collection::vec(any::<u8>(), 1..=16))] literals: Vec<u8>,
+        #[strategy(0..=20usize)] offset: usize,
+        #[strategy(0..=40usize)] length: usize,
+    ) {
+        let mut buffer = vec![];
+        let mut vec_sink = VecSink::new(&mut buffer);
+        let vec_result = vec_sink
+            .literals(&literals)
+            .and_then(|()| vec_sink.copy(offset, length));
+
+        let mut out = vec![0; literals.len() + length];
+        let mut slice_sink = SliceSink {
+            out: &mut out,
+            written: 0,
+        };
+        let slice_result = slice_sink
+            .literals(&literals)
+            .and_then(|()| slice_sink.copy(offset, length));
+
+        let mut count_sink = CountSink::default();
+        let count_result = count_sink
+            .literals(&literals)
+            .and_then(|()| count_sink.copy(offset, length));
+
+        prop_assert_eq!(vec_result.is_ok(), slice_result.is_ok());
+        prop_assert_eq!(vec_result.is_ok(), count_result.is_ok());
+        if vec_result.is_ok() {
+            prop_assert_eq!(&buffer, &out);
+            prop_assert_eq!(count_sink.produced, out.len());
+        }
+    }
+}
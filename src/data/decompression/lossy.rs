@@ -0,0 +1,232 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Best effort decompression of corrupt or truncated data
+
+use std::cmp::min;
+use std::io::ErrorKind;
+
+use crate::RefPackError;
+use crate::data::decompression::decode::{SliceSource, Source, VecSink, write_control};
+use crate::format::Format;
+use crate::header::Header;
+
+/// Result of [decompress_lossy]: everything that could be recovered from the
+/// input, along with where and why decoding stopped.
+#[derive(Debug)]
+pub struct DecompressionOutcome {
+    /// The header of the input, or `None` if the header itself could not be
+    /// read
+    pub header: Option<Header>,
+    /// Output decoded before decoding stopped. If zero filling was requested,
+    /// this is padded with zeroes up to the decompressed length in the header.
+    pub output: Vec<u8>,
+    /// The error that stopped decoding, or `None` if the stopcode was reached
+    pub error: Option<RefPackError>,
+    /// Offset in the input where decoding stopped. On error this is the start
+    /// of the control that failed to decode.
+    pub compressed_offset: usize,
+    /// Number of bytes at the start of `output` that were actually decoded,
+    /// not counting any zero filling
+    pub decompressed_offset: usize,
+}
+
+impl DecompressionOutcome {
+    /// Returns true if the input was decoded all the way to the stopcode
+    /// without any errors
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Decompress as much of `refpack` data as possible, returning what could be
+/// decoded even when the data is corrupt or truncated.
+///
+/// Where [easy_decompress](crate::easy_decompress) discards everything once
+/// an error is hit, this keeps all output decoded up to the failing control,
+/// as well as any of its literal bytes that were present. This is intended for
+/// recovering what is left of damaged data, so the error is reported in the
+/// outcome rather than returned.
+///
+/// If `zero_fill` is true, the output is padded with zeroes up to the
+/// decompressed length in the header. Note that the header is trusted for
+/// this, so only enable it for input where that is acceptable.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::Reference;
+/// use refpack::{CompressionOptions, decompress_lossy, easy_compress};
+///
+/// let input = b"Hello World! Hello World! Hello World!";
+/// let compressed = easy_compress::<Reference>(input, CompressionOptions::Fast).unwrap();
+///
+/// // lose the end of the data
+/// let outcome = decompress_lossy::<Reference>(&compressed[..compressed.len() - 4], true);
+///
+/// assert!(!outcome.is_complete());
+/// assert_eq!(outcome.output.len(), input.len());
+/// assert_eq!(
+///     &outcome.output[..outcome.decompressed_offset],
+///     &input[..outcome.decompressed_offset]
+/// );
+/// ```
+#[must_use]
+pub fn decompress_lossy<F: Format>(input: &[u8], zero_fill: bool) -> DecompressionOutcome {
+    let mut reader = input;
+
+    let header = match Header::read::<F::HeaderMode>(&mut reader) {
+        Ok(header) => header,
+        Err(error) => {
+            return DecompressionOutcome {
+                header: None,
+                output: vec![],
+                error: Some(error),
+                compressed_offset: 0,
+                decompressed_offset: 0,
+            };
+        }
+    };
+
+    let mut output = vec![];
+    let mut source = SliceSource {
+        input,
+        position: input.len() - reader.len(),
+    };
+    let mut compressed_offset = source.position;
+    let error = decode_lossy(&mut source, &mut output, &mut compressed_offset).err();
+    let decompressed_offset = output.len();
+
+    if zero_fill {
+        let decompressed_length = header.decompressed_length as usize;
+        if decompressed_length > output.len() {
+            output.resize(decompressed_length, 0);
+        }
+    }
+
+    DecompressionOutcome {
+        header: Some(header),
+        output,
+        error,
+        compressed_offset,
+        decompressed_offset,
+    }
+}
+
+/// Decode controls into `output` until the stopcode or an error is hit.
+/// `compressed_offset` is kept at the start of the control being decoded.
+fn decode_lossy(
+    source: &mut SliceSource,
+    output: &mut Vec<u8>,
+    compressed_offset: &mut usize,
+) -> Result<(), RefPackError> {
+    let mut sink = VecSink::new(output);
+
+    loop {
+        let command = source.command()?;
+
+        // copy as many of the literals as are present, so truncated input
+        // still yields all the data it contains
+        let literal = command.literal as usize;
+        let available = min(literal, source.input.len() - source.position);
+        let literals = source.literals(available)?;
+
+        if available < literal {
+            sink.buffer.extend_from_slice(literals);
+            return Err(RefPackError::Io(ErrorKind::UnexpectedEof.into()));
+        }
+
+        write_control(&mut sink, command, literals)?;
+
+        *compressed_offset = source.position;
+
+        if command.is_stop() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::DecodeError;
+    use crate::data::compression::CompressionOptions;
+    use crate::easy_compress;
+    use crate::format::Reference;
+
+    #[proptest]
+    fn symmetrical_lossy(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+        let outcome = decompress_lossy::<Reference>(&compressed, true);
+
+        prop_assert!(outcome.is_complete());
+        prop_assert_eq!(outcome.compressed_offset, compressed.len());
+        prop_assert_eq!(outcome.decompressed_offset, input.len());
+        prop_assert_eq!(outcome.output, input);
+    }
+
+    #[proptest]
+    fn truncated_yields_prefix(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+        #[strategy(0..=#input.len())] cut: usize,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+        let cut = min(cut, compressed.len() - 1);
+        let outcome = decompress_lossy::<Reference>(&compressed[..cut], false);
+
+        prop_assert!(!outcome.is_complete());
+        prop_assert!(outcome.compressed_offset <= cut);
+        prop_assert_eq!(outcome.decompressed_offset, outcome.output.len());
+        prop_assert_eq!(&outcome.output[..], &input[..outcome.output.len()]);
+    }
+
+    #[test]
+    fn keeps_output_before_bad_offset() {
+        // 4 byte literal, then a short copy reaching back 8 bytes
+        let data = [0x10, 0x00, 0x00, 0x00, 0xE0, 1, 2, 3, 4, 0x00, 0x07];
+        let outcome = decompress_lossy::<Reference>(&data, true);
+
+        assert!(matches!(
+            outcome.error,
+            Some(RefPackError::ControlError {
+                error: DecodeError::NegativePosition(4, 8),
+                position: 4,
+            })
+        ));
+        assert_eq!(outcome.compressed_offset, 9);
+        assert_eq!(outcome.decompressed_offset, 4);
+        assert_eq!(outcome.output.len(), 16);
+        assert_eq!(&outcome.output[..4], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn keeps_partial_literal() {
+        let data = [0x10, 0x00, 0x00, 0x00, 0xE1, 1, 2, 3, 4, 5];
+        let outcome = decompress_lossy::<Reference>(&data, false);
+
+        assert!(matches!(outcome.error, Some(RefPackError::Io(_))));
+        assert_eq!(outcome.compressed_offset, 4);
+        assert_eq!(outcome.output, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn reports_bad_header() {
+        let outcome = decompress_lossy::<Reference>(&[0x00, 0x00], true);
+
+        assert!(outcome.header.is_none());
+        assert!(matches!(outcome.error, Some(RefPackError::Io(_))));
+        assert!(outcome.output.is_empty());
+    }
+}
@@ -0,0 +1,897 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Decompression parsing, algorithms, and functionality. Exact decompression
+//! algorithm is subject to change.
+//!
+//! Basic concept is to parse the header, identify key information such as
+//! decompressed header, then parse as a repeating stream of "command" blocks,
+//! consisting of a control code and any (if any) following literal bytes.
+//!
+//! Literal bytes should always be written before performing the control code
+//! operation.
+//!
+//! Control code operations are "run length encoded", which is a format of
+//! encoding where the "length" of a pointer-style control can be longer than
+//! the lookback length. This indicates that the section within the lookback
+//! region should repeat until the length is fulfilled
+//!
+//! # Example Byte-by-byte Algorithm
+//!
+//! This is *normally* handled via byte-by-byte decoding, where a lookback
+//! position and write position counter are incremented at the same time,
+//! however other faster implementations may be possible. This algorithm
+//! explanation is purely intended to illustrate the concept.
+//!
+//! ## Algorithm steps
+//!
+//! while in reality the possible allowed values are any arbitrary byte,
+//! characters are used to indicate whole bytes to simplify illustration
+//!
+//! Given the current decoded output of `DEADBEEF`, the next control encountered
+//! has a lookback of `4`, and a length of `16`.
+//!
+//! ### 1. Create Pointers
+//! Create pointers/get indexes for the current lookback position and current
+//! output position
+//!
+//! ```text
+//! DEADBEEF
+//!     ^   ^
+//!     LB  O
+//! ```
+//!
+//! ### 2. Copy from lookback to output
+//! Take the current byte at the lookback position, and copy it to the output
+//! position
+//!
+//! ```text
+//! DEADBEEFB
+//!     ^   ^
+//!     LB  O
+//! ```
+//!
+//! ### 3. Advance Pointers
+//! Increment both the lookback position and the output position
+//!
+//! ```text
+//! DEADBEEFB
+//!      ^   ^
+//!      LB  O
+//! ```
+//!
+//! ### 4. Repeat `length` times
+//! Steps 2 and 3 should repeat a total of times equal to the length of the
+//! control block.
+//!
+//! #### 4.A "What about when the lookback reaches already output?"
+//! In a case where the length overruns into the already written output, the
+//! process continues as normal and the output starts to repeat.
+//!
+//! in this example output, On the 5th iteration you will encounter this state:
+//! ```text
+//! DEADBEEFBEEF
+//!         ^   ^
+//!         LB  O
+//! ```
+//! The lookback is currently pointing at the first "B" that has been written.
+//! This isn't actually a problem, and step 2 and 3 should be followed as normal:
+//!
+//! ```text
+//! DEADBEEFBEEFB
+//!         ^   ^
+//!         LB  O
+//! ```
+//!
+//! ```text
+//! DEADBEEFBEEFB
+//!          ^   ^
+//!          LB  O
+//! ```
+//!
+//! ## Final Result
+//! Given that the algorithm was followed properly, the final output of the
+//! example input should be
+//! ```text
+//! DEADBEEFBEEFBEEFBEEFBEEF
+//! ```
+
+mod decode;
+mod lossy;
+mod push;
+pub(crate) mod slice;
+mod stream;
+mod validate;
+
+use std::cmp::{max, min};
+use std::io::{BufRead, Read, Write};
+
+pub use lossy::{DecompressionOutcome, decompress_lossy};
+pub use push::{Decoder, Progress};
+pub use stream::RefPackDecoder;
+pub use validate::{StreamSummary, Violation, validate};
+
+use crate::data::decompression::decode::{
+    BufReadSource,
+    LimitedSink,
+    ReaderSource,
+    Source,
+    VecSink,
+    write_control,
+};
+use crate::data::decompression::slice::{decode_slice_into, decompress_slice_append};
+use crate::format::Format;
+use crate::header::Header;
+use crate::{RefPackError, RefPackResult};
+
+/// Limits on how much memory decompression is allowed to use, and how strictly
+/// the data is checked
+///
+/// The decompressed length in the header is read from the input, so when
+/// decompressing untrusted data a bogus header can otherwise cause an
+/// arbitrarily large allocation. Exceeding any of the limits results in a
+/// [RefPackError::LimitExceeded] instead of more memory being allocated.
+///
+/// The default has no limits, trusts the header length and isn't strict,
+/// which is the behaviour of [decompress] and [easy_decompress].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DecompressionOptions {
+    /// Maximum amount of bytes decompression is allowed to produce
+    pub max_output: Option<usize>,
+    /// Maximum ratio of decompressed bytes to compressed bytes. This is
+    /// checked against the amount of compressed bytes that have been read at
+    /// the point an allocation is made.
+    ///
+    /// RefPack itself can not reach a ratio much higher than `257`, so limits
+    /// above that will never reject any valid data.
+    pub max_ratio: Option<usize>,
+    /// Whether to allocate the decompressed length from the header up front.
+    ///
+    /// When `false`, the output buffer starts out empty and grows as data is
+    /// decompressed. When `true` the allocation is still capped by the other
+    /// limits.
+    pub trust_header_length: bool,
+    /// Whether to error if the data does not end exactly at the stopcode, or
+    /// if the amount of compressed bytes read disagrees with the compressed
+    /// length in the header.
+    ///
+    /// Checking for trailing data requires reading one byte past the stopcode,
+    /// so this should only be enabled when nothing else is expected to follow
+    /// in the reader. Either check failing results in a
+    /// [RefPackError::Validation].
+    pub strict: bool,
+}
+
+impl Default for DecompressionOptions {
+    fn default() -> Self {
+        Self {
+            max_output: None,
+            max_ratio: None,
+            trust_header_length: true,
+            strict: false,
+        }
+    }
+}
+
+impl DecompressionOptions {
+    /// The maximum amount of output allowed after reading `consumed`
+    /// compressed bytes
+    fn limit(&self, consumed: usize) -> usize {
+        let output_limit = self.max_output.unwrap_or(usize::MAX);
+        let ratio_limit = self
+            .max_ratio
+            .map_or(usize::MAX, |ratio| ratio.saturating_mul(consumed));
+        min(output_limit, ratio_limit)
+    }
+}
+
+/// Information about a completed decompression
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DecompressionReport {
+    /// The header of the data
+    pub header: Header,
+    /// Number of bytes read from the input, including the header and up to and
+    /// including the stopcode. In an archive, this is where the next record
+    /// starts.
+    pub consumed: usize,
+    /// Number of bytes of output that were produced
+    pub produced: usize,
+}
+
+/// Whether the compressed length in `header` matches `consumed` bytes of input,
+/// of which the first `header_length` were the header. Implementations differ
+/// in whether the header is counted, so both are accepted.
+pub(crate) fn compressed_length_matches(
+    header: &Header,
+    header_length: usize,
+    consumed: usize,
+) -> bool {
+    header.compressed_length.is_none_or(|length| {
+        length as usize == consumed - header_length || length as usize == consumed
+    })
+}
+
+// Returning the internal buffer is the fastest way to return the data
+// since that way the buffer doesn't have to be copied,
+// this function is used to reach optimal performance
+fn decompress_internal<F: Format>(
+    reader: &mut impl Read,
+    options: DecompressionOptions,
+) -> RefPackResult<(Vec<u8>, DecompressionReport)> {
+    let mut decompression_buffer = vec![];
+    let report = decompress_append::<F>(reader, &mut decompression_buffer, options)?;
+    Ok((decompression_buffer, report))
+}
+
+/// Decompress into the end of `buffer`, leaving any existing contents intact.
+/// Copy commands are not able to reach back into the existing contents.
+///
+/// On error `buffer` is truncated back to its original length
+fn decompress_append<F: Format>(
+    reader: &mut impl Read,
+    buffer: &mut Vec<u8>,
+    options: DecompressionOptions,
+) -> RefPackResult<DecompressionReport> {
+    let mut source = ReaderSource::new(reader);
+    let header = Header::read::<F::HeaderMode>(&mut source.reader)?;
+    decode_append(&mut source, header, buffer, options)
+}
+
+/// [decompress_append] parsing controls and literals straight from the buffer
+/// of `reader`
+fn decompress_buffered_append<F: Format>(
+    reader: &mut impl BufRead,
+    buffer: &mut Vec<u8>,
+    options: DecompressionOptions,
+) -> RefPackResult<DecompressionReport> {
+    let mut source = BufReadSource::new(reader);
+    let header = Header::read::<F::HeaderMode>(&mut source)?;
+    decode_append(&mut source, header, buffer, options)
+}
+
+/// Decode the controls from `source` onto the end of `buffer`, leaving any
+/// existing contents intact. `header` is the header that was read from
+/// `source` before.
+///
+/// On error `buffer` is truncated back to its original length
+pub(crate) fn decode_append(
+    source: &mut impl Source,
+    header: Header,
+    buffer: &mut Vec<u8>,
+    options: DecompressionOptions,
+) -> RefPackResult<DecompressionReport> {
+    let start = buffer.len();
+    let result = decode_sink(source, header, &mut VecSink::new(buffer), options);
+    if result.is_err() {
+        buffer.truncate(start);
+    }
+    result
+}
+
+fn decode_sink(
+    source: &mut impl Source,
+    header: Header,
+    sink: &mut VecSink,
+    options: DecompressionOptions,
+) -> RefPackResult<DecompressionReport> {
+    let header_length = source.consumed();
+
+    // the amount of output that allocations have been allowed for
+    let mut allowed = if options.trust_header_length {
+        min(
+            header.decompressed_length as usize,
+            options.limit(header_length),
+        )
+    } else {
+        0
+    };
+    sink.buffer.reserve(allowed);
+
+    loop {
+        let command = source.command()?;
+        let literal = command.literal as usize;
+
+        let produced = sink.produced();
+        let needed = produced + literal + command.length as usize;
+        if needed > allowed {
+            // the literals of the command are going to be read as well
+            let limit = options.limit(source.consumed() + literal);
+            if needed > limit {
+                return Err(RefPackError::LimitExceeded {
+                    limit,
+                    length: needed,
+                });
+            }
+            allowed = min(max(needed, allowed * 2), limit);
+            sink.buffer.reserve(allowed - produced);
+        }
+
+        let literals = source.literals(literal)?;
+        write_control(sink, command, literals)?;
+
+        if command.is_stop() {
+            break;
+        }
+    }
+
+    let consumed = source.consumed();
+    let produced = sink.produced();
+
+    if options.strict {
+        let violation = |violation| {
+            RefPackError::Validation {
+                violation,
+                compressed_position: consumed,
+                decompressed_position: produced,
+            }
+        };
+
+        if !compressed_length_matches(&header, header_length, consumed) {
+            return Err(violation(Violation::CompressedLength {
+                header: header.compressed_length.unwrap_or(0),
+                actual: consumed - header_length,
+            }));
+        }
+        if !source.is_at_end()? {
+            return Err(violation(Violation::TrailingData));
+        }
+    }
+
+    Ok(DecompressionReport {
+        header,
+        consumed,
+        produced,
+    })
+}
+
+/// Decompress `refpack` data. Accepts arbitrary `Read`s and `Write`s.
+///
+/// When the compressed data is already in memory, prefer [easy_decompress] or
+/// [decompress_into_vec]. Those parse the data straight from the slice, which
+/// is considerably faster than reading it through a reader. `decompress` has no
+/// way of telling that a reader is a `Cursor` over a slice, so it can't switch
+/// to this by itself, but [decompress_buffered] can be used for readers that
+/// implement `BufRead`.
+///
+/// # Example
+///
+/// ```Rust
+/// use std::io::Cursor;
+///
+/// let mut input = Cursor::new(/* some refpack data */);
+/// let mut output = Cursor::new(Vec::new());
+///
+/// // decompress the input into the output
+/// refpack::compress(&mut input, &mut output);
+/// // output now contains the decompressed version of the input
+/// ```
+///
+/// # Returns
+/// a [DecompressionReport] with the amount of bytes read and written
+///
+/// # Errors
+/// - [RefPackError::BadMagic]: Header magic was malformed, likely indicating
+///   either uncompressed data or attempting to decompress data in an incorrect
+///   format
+/// - [RefPackError::BadFlags]: Header magic was malformed, likely indicating
+///   either uncompressed data or attempting to decompress data in an incorrect
+///   format
+/// - [RefPackError::ControlError]: Invalid control code operation was attempted
+///   to be performed. This normally indicated corrupted or invalid refpack
+///   data
+/// - [RefPackError::Io]: Generic IO error occured while attempting to read or
+///   write data
+pub fn decompress<F: Format>(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+) -> RefPackResult<DecompressionReport> {
+    decompress_with_options::<F>(reader, writer, DecompressionOptions::default())
+}
+
+/// Decompress `refpack` data with the limits in `options` applied. Otherwise
+/// equivalent to [decompress].
+///
+/// # Errors
+/// - [RefPackError::LimitExceeded]: Decompressing would exceed one of the
+///   limits in `options`
+/// - [RefPackError::Validation]: In strict mode, the data was followed by
+///   trailing data or the compressed length in the header didn't match
+/// - See [decompress] for all other errors
+pub fn decompress_with_options<F: Format>(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    options: DecompressionOptions,
+) -> RefPackResult<DecompressionReport> {
+    let (data, report) = decompress_internal::<F>(reader, options)?;
+
+    writer.write_all(data.as_slice())?;
+    writer.flush()?;
+
+    Ok(report)
+}
+
+/// Decompress `refpack` data from a `BufRead`. Otherwise equivalent to
+/// [decompress].
+///
+/// Controls and literals are parsed straight from the buffer of `reader`
+/// whenever it holds them in full. For input that is already in memory, like a
+/// `&[u8]` or a `Cursor` over one, that is always the case, so this is as fast
+/// as decompressing the slice with [easy_decompress]. Only the input up to and
+/// including the stopcode is consumed from `reader`.
+///
+/// # Errors
+/// See [decompress]
+pub fn decompress_buffered<F: Format>(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+) -> RefPackResult<DecompressionReport> {
+    decompress_buffered_with_options::<F>(reader, writer, DecompressionOptions::default())
+}
+
+/// Decompress `refpack` data from a `BufRead` with the limits in `options`
+/// applied. Otherwise equivalent to [decompress_buffered].
+///
+/// # Errors
+/// See [decompress_with_options]
+pub fn decompress_buffered_with_options<F: Format>(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    options: DecompressionOptions,
+) -> RefPackResult<DecompressionReport> {
+    let mut data = vec![];
+    let report = decompress_buffered_append::<F>(reader, &mut data, options)?;
+
+    writer.write_all(data.as_slice())?;
+    writer.flush()?;
+
+    Ok(report)
+}
+
+/// Wrapped decompress function with a bit easier and cleaner of an API.
+/// Takes a slice of bytes and returns a Vec of byes
+/// In implementation this just creates `Cursor`s for the reader and writer and
+/// calls `decompress`
+///
+/// # Returns
+///
+/// A Result containing either `Vec<u8>` of the decompressed data or a
+/// `RefPackError`.
+///
+/// # Errors
+/// - [RefPackError::BadMagic]: Header was malformed, likely indicating either
+///   uncompressed data or attempting to decompress data in an incorrect format
+/// - [RefPackError::BadFlags]: Header magic was malformed, likely indicating
+///   either uncompressed data or attempting to decompress data in an incorrect
+///   format
+/// - [RefPackError::ControlError]: Invalid control code operation was attempted
+///   to be performed. This normally indicated corrupted or invalid refpack
+///   data
+/// - [RefPackError::Io]: Generic IO error occured while attempting to read or
+///   write data
+#[inline]
+pub fn easy_decompress<F: Format>(input: &[u8]) -> Result<Vec<u8>, RefPackError> {
+    easy_decompress_with_options::<F>(input, DecompressionOptions::default())
+}
+
+/// Wrapped [decompress_with_options] function, taking a slice of bytes and
+/// returning a Vec of bytes. Otherwise equivalent to [easy_decompress].
+///
+/// # Errors
+/// - [RefPackError::LimitExceeded]: Decompressing would exceed one of the
+///   limits in `options`
+/// - See [easy_decompress] for all other errors
+#[inline]
+pub fn easy_decompress_with_options<F: Format>(
+    input: &[u8],
+    options: DecompressionOptions,
+) -> Result<Vec<u8>, RefPackError> {
+    let mut data = vec![];
+    decompress_slice_append::<F>(input, &mut data, options)?;
+    Ok(data)
+}
+
+/// Decompress `refpack` data into a caller provided buffer without allocating.
+///
+/// The header can be read beforehand to size the buffer, the decompressed
+/// length in the header is otherwise ignored.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// use refpack::format::{Format, Reference};
+/// use refpack::header::Header;
+/// use refpack::{CompressionOptions, decompress_into, easy_compress};
+///
+/// let compressed = easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+///
+/// let header =
+///     Header::read::<<Reference as Format>::HeaderMode>(&mut Cursor::new(&compressed)).unwrap();
+/// let mut out = vec![0; header.decompressed_length as usize];
+///
+/// let length = decompress_into::<Reference>(&compressed, &mut out).unwrap();
+/// assert_eq!(&out[..length], b"Hello World!");
+/// ```
+///
+/// # Returns
+/// the number of bytes written to the start of `out`
+///
+/// # Errors
+/// - [RefPackError::ControlError]: `out` is too small to hold the decompressed
+///   data, in which case the error is [DecodeError::BadLength], or an invalid
+///   control code operation was attempted to be performed
+/// - See [easy_decompress] for all other errors
+///
+/// [DecodeError::BadLength]: crate::data::DecodeError::BadLength
+pub fn decompress_into<F: Format>(input: &[u8], out: &mut [u8]) -> RefPackResult<usize> {
+    let mut reader = input;
+    Header::read::<F::HeaderMode>(&mut reader)?;
+    decode_slice_into(input, input.len() - reader.len(), out)
+}
+
+/// Decompress `refpack` data by appending it to the end of `out`.
+///
+/// Allows reusing the same buffer for many decompressions by clearing it in
+/// between. Existing contents of `out` are left intact, and `out` is truncated
+/// back to its original length when an error occurs.
+///
+/// # Returns
+/// the number of bytes appended to `out`
+///
+/// # Errors
+/// See [easy_decompress]
+pub fn decompress_into_vec<F: Format>(input: &[u8], out: &mut Vec<u8>) -> RefPackResult<usize> {
+    decompress_slice_append::<F>(input, out, DecompressionOptions::default())
+        .map(|report| report.produced)
+}
+
+/// Decompress only the first `n` bytes of `refpack` data.
+///
+/// Controls stop being read as soon as `n` bytes have been produced, with the
+/// final literal or copy command cut short as needed. Reading from `reader`
+/// stops at the last byte that was needed, so nothing past that is consumed.
+/// Useful when only something like a type tag at the start of a resource is
+/// of interest.
+///
+/// If the stopcode is reached before `n` bytes have been produced, the entire
+/// decompressed data is returned.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::Reference;
+/// use refpack::{CompressionOptions, decompress_prefix, easy_compress};
+///
+/// let compressed = easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+///
+/// let (prefix, _) = decompress_prefix::<Reference>(&mut compressed.as_slice(), 5).unwrap();
+/// assert_eq!(prefix, b"Hello");
+/// ```
+///
+/// # Returns
+/// the decompressed prefix, and the number of bytes that were read from
+/// `reader` including the header
+///
+/// # Errors
+/// See [decompress]
+pub fn decompress_prefix<F: Format>(
+    reader: &mut impl Read,
+    n: usize,
+) -> RefPackResult<(Vec<u8>, usize)> {
+    let mut source = ReaderSource::new(reader);
+
+    let Header {
+        decompressed_length,
+        ..
+    } = Header::read::<F::HeaderMode>(&mut source.reader)?;
+
+    let mut buffer = Vec::with_capacity(min(n, decompressed_length as usize));
+    let mut sink = LimitedSink {
+        sink: VecSink::new(&mut buffer),
+        limit: n,
+    };
+
+    while sink.remaining() > 0 {
+        let command = source.command()?;
+
+        // only read the literals that end up in the prefix, so nothing past
+        // the last needed byte is consumed
+        let literals = source.literals(min(command.literal as usize, sink.remaining()))?;
+        write_control(&mut sink, command, literals)?;
+
+        if command.is_stop() {
+            break;
+        }
+    }
+
+    Ok((buffer, source.consumed()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Cursor, Seek};
+
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::DecodeError;
+    use crate::data::compression::CompressionOptions;
+    use crate::easy_compress;
+    use crate::format::{Maxis, Reference};
+
+    /// Reference header claiming `u32::MAX` bytes, followed by a 4 byte literal
+    /// and a copy command of 1000 bytes
+    fn lying_header_data() -> Vec<u8> {
+        vec![
+            0xFF, 0xFF, 0xFF, 0xFF, 0xE0, 1, 2, 3, 4, 0xCC, 0x00, 0x03, 0xE3, 0xFC,
+        ]
+    }
+
+    #[proptest]
+    fn symmetrical_without_trusting_header(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+        let options = DecompressionOptions {
+            trust_header_length: false,
+            ..Default::default()
+        };
+        let got = easy_decompress_with_options::<Reference>(&compressed, options)?;
+
+        prop_assert_eq!(got, input);
+    }
+
+    #[proptest]
+    fn symmetrical_without_seek(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+        // `&[u8]` is `Read` but not `Seek`
+        let mut reader = compressed.as_slice();
+        let mut got = Cursor::new(vec![]);
+        decompress::<Reference>(&mut reader, &mut got)?;
+
+        prop_assert!(reader.is_empty());
+        prop_assert_eq!(got.into_inner(), input);
+    }
+
+    #[proptest]
+    fn symmetrical_through_small_buffer(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        #[strategy(proptest::collection::vec(any::<u8>(), 0..=10))] trailing: Vec<u8>,
+        compression_options: CompressionOptions,
+        #[strategy(1..=16usize)] capacity: usize,
+    ) {
+        let compressed = easy_compress::<Maxis>(&input, compression_options)?;
+        let data = [compressed.as_slice(), &trailing].concat();
+        // commands and literals end up split across the buffer of the reader
+        let mut reader = BufReader::with_capacity(capacity, Cursor::new(&data));
+        let mut got = vec![];
+        let report = decompress_buffered::<Maxis>(&mut reader, &mut got)?;
+
+        prop_assert_eq!(report.consumed, compressed.len());
+        prop_assert_eq!(reader.stream_position()?, compressed.len() as u64);
+        prop_assert_eq!(got, input);
+    }
+
+    #[proptest]
+    fn symmetrical_into_slice(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+        let mut out = vec![0; input.len()];
+        let length = decompress_into::<Reference>(&compressed, &mut out)?;
+
+        prop_assert_eq!(length, input.len());
+        prop_assert_eq!(out, input);
+    }
+
+    #[proptest]
+    fn symmetrical_into_vec(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        #[strategy(proptest::collection::vec(any::<u8>(), 0..=100))] existing: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+        let mut out = existing.clone();
+        let length = decompress_into_vec::<Reference>(&compressed, &mut out)?;
+
+        prop_assert_eq!(length, input.len());
+        prop_assert_eq!(&out[..existing.len()], &existing[..]);
+        prop_assert_eq!(&out[existing.len()..], &input[..]);
+    }
+
+    #[proptest]
+    fn prefix_matches_input(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+        #[strategy(0..=#input.len() + 10)] n: usize,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+        let mut reader = Cursor::new(&compressed);
+        let (prefix, consumed) = decompress_prefix::<Reference>(&mut reader, n)?;
+
+        prop_assert_eq!(&prefix[..], &input[..min(n, input.len())]);
+        prop_assert_eq!(consumed as u64, reader.position());
+        if n > input.len() {
+            prop_assert_eq!(consumed, compressed.len());
+        }
+    }
+
+    #[test]
+    fn prefix_stops_reading_early() {
+        let data = lying_header_data();
+        let mut reader = data.as_slice();
+        let (prefix, consumed) = decompress_prefix::<Reference>(&mut reader, 6).unwrap();
+
+        assert_eq!(prefix, [1, 2, 3, 4, 1, 2]);
+        // the final stopcode is never read
+        assert_eq!(consumed, data.len() - 1);
+        assert_eq!(reader, [0xFC]);
+    }
+
+    #[test]
+    fn errors_on_too_small_buffer() {
+        let compressed =
+            easy_compress::<Reference>(&[0; 100], CompressionOptions::Optimal).unwrap();
+        let mut out = [0; 99];
+        let error = decompress_into::<Reference>(&compressed, &mut out).unwrap_err();
+        assert!(matches!(
+            error,
+            RefPackError::ControlError {
+                error: DecodeError::BadLength(1),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn into_vec_does_not_reach_into_existing_contents() {
+        // copy command with an offset of 1 directly after the header
+        let data = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFC];
+        let mut out = vec![1, 2, 3];
+        let error = decompress_into_vec::<Reference>(&data, &mut out).unwrap_err();
+        assert!(matches!(
+            error,
+            RefPackError::ControlError {
+                error: DecodeError::NegativePosition(0, 1),
+                ..
+            }
+        ));
+        assert_eq!(out, [1, 2, 3]);
+    }
+
+    #[proptest]
+    fn reports_consumed_length(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        #[strategy(proptest::collection::vec(any::<u8>(), 0..=100))] trailing: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Maxis>(&input, compression_options)?;
+        let mut data = compressed.clone();
+        data.extend_from_slice(&trailing);
+
+        let mut reader = data.as_slice();
+        let mut out = vec![];
+        let report = decompress::<Maxis>(&mut reader, &mut out)?;
+
+        prop_assert_eq!(report.consumed, compressed.len());
+        prop_assert_eq!(report.produced, input.len());
+        prop_assert_eq!(report.header.decompressed_length as usize, input.len());
+        prop_assert_eq!(reader, &trailing[..]);
+        prop_assert_eq!(out, input);
+    }
+
+    #[proptest]
+    fn strict_accepts_exact_data(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Maxis>(&input, compression_options)?;
+        let options = DecompressionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let got = easy_decompress_with_options::<Maxis>(&compressed, options)?;
+
+        prop_assert_eq!(got, input);
+    }
+
+    #[test]
+    fn strict_errors_on_trailing_data() {
+        let mut compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let length = compressed.len();
+        compressed.push(0);
+        let options = DecompressionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let error = easy_decompress_with_options::<Reference>(&compressed, options).unwrap_err();
+
+        assert!(matches!(
+            error,
+            RefPackError::Validation {
+                violation: Violation::TrailingData,
+                compressed_position,
+                decompressed_position: 12,
+            } if compressed_position == length
+        ));
+    }
+
+    #[test]
+    fn strict_errors_on_compressed_length_mismatch() {
+        let mut compressed =
+            easy_compress::<Maxis>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        compressed[0] += 1;
+        let options = DecompressionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let error = easy_decompress_with_options::<Maxis>(&compressed, options).unwrap_err();
+
+        assert!(matches!(
+            error,
+            RefPackError::Validation {
+                violation: Violation::CompressedLength { .. },
+                ..
+            }
+        ));
+        // without strict mode the length is ignored
+        assert!(easy_decompress::<Maxis>(&compressed).is_ok());
+    }
+
+    #[test]
+    fn caps_header_allocation() {
+        let options = DecompressionOptions {
+            max_output: Some(2000),
+            ..Default::default()
+        };
+        let got = easy_decompress_with_options::<Reference>(&lying_header_data(), options).unwrap();
+        assert_eq!(got.len(), 1004);
+    }
+
+    #[test]
+    fn errors_on_exceeding_max_output() {
+        let options = DecompressionOptions {
+            max_output: Some(1000),
+            ..Default::default()
+        };
+        let error =
+            easy_decompress_with_options::<Reference>(&lying_header_data(), options).unwrap_err();
+        assert!(matches!(
+            error,
+            RefPackError::LimitExceeded {
+                limit: 1000,
+                length: 1004
+            }
+        ));
+    }
+
+    #[test]
+    fn errors_on_exceeding_max_ratio() {
+        let options = DecompressionOptions {
+            max_ratio: Some(10),
+            ..Default::default()
+        };
+        // 13 bytes have been read by the time the copy command needs space
+        let error =
+            easy_decompress_with_options::<Reference>(&lying_header_data(), options).unwrap_err();
+        assert!(matches!(
+            error,
+            RefPackError::LimitExceeded {
+                limit: 130,
+                length: 1004
+            }
+        ));
+    }
+}
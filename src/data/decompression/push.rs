@@ -0,0 +1,497 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Decompression of data that is pushed to the decoder as it arrives
+//!
+//! [Decoder] doesn't do any IO itself. Input is handed to it in chunks, which
+//! can split the header, a control or its literals at any point, and the
+//! decoder keeps track of where it left off between chunks. This makes it
+//! usable in places that can't block on a [Read](std::io::Read), such as event
+//! loops.
+
+use std::cmp::min;
+use std::io::ErrorKind;
+
+use crate::data::control::Command;
+use crate::data::decompression::decode::{VecSink, write_copy, write_literals};
+use crate::data::decompression::stream::{WINDOW_SIZE, WINDOW_SLACK};
+use crate::format::Format;
+use crate::header::Header;
+use crate::{RefPackError, RefPackResult};
+
+/// Amount of input moved over at a time while a header or control is split
+/// across chunks. Both are only a few bytes long, so this avoids copying large
+/// chunks when only their start is needed.
+const PENDING_STEP: usize = 16;
+
+/// What the decoder expects next from the input
+#[derive(Copy, Clone, Debug)]
+enum State {
+    Header,
+    Command,
+    /// The literals of `command`, of which `remaining` haven't arrived yet
+    Literal {
+        command: Command,
+        remaining: usize,
+    },
+    Finished,
+}
+
+/// The result of feeding a chunk to a [Decoder]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Progress {
+    /// Number of bytes from the start of the chunk that were used. This is the
+    /// entire chunk, unless the stopcode was reached before the end of it.
+    pub consumed: usize,
+    /// Number of bytes of output that were produced
+    pub produced: usize,
+    /// Whether the stopcode has been decoded
+    pub finished: bool,
+}
+
+/// Decompresses `refpack` data that is pushed to it in chunks.
+///
+/// Each chunk passed to [feed](Self::feed) is decoded as far as possible, and
+/// anything that is cut off at the end of it, like part of a control or a run
+/// of literals, is picked up again with the next chunk. Output is kept in an
+/// internal window until it's taken with [output](Self::output) and
+/// [consume_output](Self::consume_output). Only the last [LONG_OFFSET_MAX]
+/// bytes of consumed output are kept around for copy commands.
+///
+/// Decoding produces the same output and errors as [decompress].
+///
+/// If `feed` returns an error the data is corrupt and the decoder can't
+/// continue. Output produced before the error is still available.
+///
+/// [LONG_OFFSET_MAX]: crate::data::control::LONG_OFFSET_MAX
+/// [decompress]: crate::decompress
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::Reference;
+/// use refpack::{CompressionOptions, Decoder, easy_compress};
+///
+/// let compressed = easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+///
+/// let mut decoder = Decoder::new::<Reference>();
+/// let mut out = vec![];
+/// for chunk in compressed.chunks(3) {
+///     decoder.feed(chunk).unwrap();
+///     out.extend_from_slice(decoder.output());
+///     decoder.consume_output(decoder.output().len());
+/// }
+///
+/// assert!(decoder.is_finished());
+/// assert_eq!(out, b"Hello World!");
+/// ```
+pub struct Decoder {
+    read_header: fn(&[u8]) -> RefPackResult<(Header, usize)>,
+    header: Option<Header>,
+    state: State,
+    /// start of a header or control that was cut off at the end of a chunk
+    pending: Vec<u8>,
+    /// decompressed output, of which at least the last [WINDOW_SIZE] bytes
+    /// before `read_position` are kept
+    window: Vec<u8>,
+    /// position in the window of the first byte that has not been consumed
+    read_position: usize,
+    /// number of bytes that have been discarded from the front of the window
+    discarded: usize,
+}
+
+impl Decoder {
+    /// Create a new decoder for data in format `F`
+    #[must_use]
+    pub fn new<F: Format>() -> Self {
+        Self {
+            read_header: read_header::<F>,
+            header: None,
+            state: State::Header,
+            pending: vec![],
+            window: vec![],
+            read_position: 0,
+            discarded: 0,
+        }
+    }
+
+    /// The header of the data, once enough input has been fed to read it
+    #[must_use]
+    pub fn header(&self) -> Option<Header> {
+        self.header
+    }
+
+    /// Total number of decompressed bytes that have been consumed
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.discarded + self.read_position
+    }
+
+    /// Returns true once the stopcode has been decoded and all output has been
+    /// consumed
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, State::Finished) && self.read_position == self.window.len()
+    }
+
+    /// Output that has been produced and not consumed yet
+    #[must_use]
+    pub fn output(&self) -> &[u8] {
+        &self.window[self.read_position..]
+    }
+
+    /// Mark the first `amount` bytes of [output](Self::output) as consumed,
+    /// allowing the memory they take up to be reused
+    ///
+    /// # Panics
+    /// `amount` is larger than the available output
+    pub fn consume_output(&mut self, amount: usize) {
+        assert!(
+            amount <= self.window.len() - self.read_position,
+            "consumed more output than is available"
+        );
+        self.read_position += amount;
+
+        // drop output that is too far back to be referenced anymore
+        if self.read_position > WINDOW_SIZE + WINDOW_SLACK {
+            let discard = self.read_position - WINDOW_SIZE;
+            self.window.copy_within(discard.., 0);
+            self.window.truncate(self.window.len() - discard);
+            self.read_position -= discard;
+            self.discarded += discard;
+        }
+    }
+
+    /// Decode as much of `input` as possible, continuing from where the
+    /// previous chunk left off.
+    ///
+    /// # Returns
+    /// a [Progress] with the amount of input used and output produced
+    ///
+    /// # Errors
+    /// - [RefPackError::BadMagic]: Header magic was malformed
+    /// - [RefPackError::BadFlags]: Header flags were malformed
+    /// - [RefPackError::ControlError]: Invalid control code operation was
+    ///   attempted to be performed. This normally indicated corrupted or
+    ///   invalid refpack data
+    pub fn feed(&mut self, input: &[u8]) -> RefPackResult<Progress> {
+        let start = self.window.len();
+        let mut position = 0;
+
+        loop {
+            let rest = &input[position..];
+
+            match self.state {
+                State::Header => {
+                    let (header, used) = parse_split(&mut self.pending, rest, self.read_header)?;
+                    position += used;
+                    let Some(header) = header else {
+                        break;
+                    };
+                    self.header = Some(header);
+                    self.state = State::Command;
+                }
+                State::Command => {
+                    let (command, used) = parse_split(&mut self.pending, rest, read_command)?;
+                    position += used;
+                    let Some(command) = command else {
+                        break;
+                    };
+                    self.state = State::Literal {
+                        command,
+                        remaining: command.literal as usize,
+                    };
+                }
+                State::Literal { command, remaining } => {
+                    let amount = min(remaining, rest.len());
+                    write_literals(&mut self.sink(), &rest[..amount])?;
+                    position += amount;
+
+                    if amount < remaining {
+                        self.state = State::Literal {
+                            command,
+                            remaining: remaining - amount,
+                        };
+                        break;
+                    }
+                    self.finish_command(command)?;
+                }
+                State::Finished => break,
+            }
+        }
+
+        Ok(Progress {
+            consumed: position,
+            produced: self.window.len() - start,
+            finished: matches!(self.state, State::Finished),
+        })
+    }
+
+    /// The window as a sink for decoded output
+    fn sink(&mut self) -> VecSink<'_> {
+        VecSink {
+            buffer: &mut self.window,
+            start: 0,
+            discarded: self.discarded,
+        }
+    }
+
+    /// Perform the copy of a command once all of its literals have arrived
+    fn finish_command(&mut self, command: Command) -> RefPackResult<()> {
+        write_copy(&mut self.sink(), command)?;
+
+        self.state = if command.is_stop() {
+            State::Finished
+        } else {
+            State::Command
+        };
+        Ok(())
+    }
+}
+
+fn read_header<F: Format>(mut input: &[u8]) -> RefPackResult<(Header, usize)> {
+    let length = input.len();
+    let header = Header::read::<F::HeaderMode>(&mut input)?;
+    Ok((header, length - input.len()))
+}
+
+fn read_command(input: &[u8]) -> RefPackResult<(Command, usize)> {
+    let mut position = 0;
+    let command = Command::read_slice(input, &mut position)?;
+    Ok((command, position))
+}
+
+fn is_eof(error: &RefPackError) -> bool {
+    matches!(error, RefPackError::Io(error) if error.kind() == ErrorKind::UnexpectedEof)
+}
+
+/// Parse a value from `pending` followed by `input`, where `input` may end
+/// before the value does. Whatever is used from `input` is moved to `pending`
+/// until the value can be parsed, at which point `pending` is cleared again.
+///
+/// # Returns
+/// the value if it could be parsed, and the amount of bytes used from `input`
+fn parse_split<T>(
+    pending: &mut Vec<u8>,
+    input: &[u8],
+    parse: impl Fn(&[u8]) -> RefPackResult<(T, usize)>,
+) -> RefPackResult<(Option<T>, usize)> {
+    if pending.is_empty() {
+        return match parse(input) {
+            Ok((value, length)) => Ok((Some(value), length)),
+            Err(error) if is_eof(&error) => {
+                pending.extend_from_slice(input);
+                Ok((None, input.len()))
+            }
+            Err(error) => Err(error),
+        };
+    }
+
+    let previous = pending.len();
+    let mut used = 0;
+    while used < input.len() {
+        let amount = min(PENDING_STEP, input.len() - used);
+        pending.extend_from_slice(&input[used..used + amount]);
+        used += amount;
+
+        match parse(pending) {
+            Ok((value, length)) => {
+                pending.clear();
+                return Ok((Some(value), length - previous));
+            }
+            Err(error) if is_eof(&error) => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok((None, used))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::mem::discriminant;
+
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::CompressionOptions;
+    use crate::data::control::{CommandKind, Control};
+    use crate::data::decompression::DecompressionOptions;
+    use crate::easy_compress;
+    use crate::format::{Maxis, Reference};
+
+    /// Feed `data` in chunks of the given sizes, repeating them as needed,
+    /// consuming all output along the way
+    fn feed_chunked(
+        decoder: &mut Decoder,
+        data: &[u8],
+        chunk_sizes: &[usize],
+    ) -> RefPackResult<(Vec<u8>, usize)> {
+        let mut out = vec![];
+        let mut position = 0;
+        for &size in chunk_sizes.iter().cycle() {
+            let end = min(position + size, data.len());
+            let progress = decoder.feed(&data[position..end]);
+            out.extend_from_slice(decoder.output());
+            decoder.consume_output(decoder.output().len());
+
+            let progress = progress?;
+            position += progress.consumed;
+            if progress.finished || end == data.len() {
+                break;
+            }
+        }
+        Ok((out, position))
+    }
+
+    #[proptest]
+    fn matches_reader_decompression(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=10_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+        #[strategy(proptest::collection::vec(1..=64usize, 1..=8))] chunk_sizes: Vec<usize>,
+    ) {
+        let compressed = easy_compress::<Maxis>(&input, compression_options)?;
+
+        let mut decoder = Decoder::new::<Maxis>();
+        let (got, consumed) = feed_chunked(&mut decoder, &compressed, &chunk_sizes)?;
+
+        prop_assert!(decoder.is_finished());
+        prop_assert_eq!(consumed, compressed.len());
+        prop_assert_eq!(decoder.position(), input.len());
+        prop_assert_eq!(got, input);
+    }
+
+    #[proptest]
+    fn matches_reader_on_corrupt_data(
+        #[strategy(proptest::collection::vec(any::<u8>(), 0..=100))] data: Vec<u8>,
+        #[strategy(proptest::collection::vec(1..=8usize, 1..=8))] chunk_sizes: Vec<usize>,
+    ) {
+        let mut decoder = Decoder::new::<Reference>();
+        let pushed = feed_chunked(&mut decoder, &data, &chunk_sizes);
+
+        let mut from_reader = vec![];
+        let reader_result = crate::decompress_with_options::<Reference>(
+            &mut data.as_slice(),
+            &mut from_reader,
+            DecompressionOptions {
+                trust_header_length: false,
+                ..Default::default()
+            },
+        );
+
+        // running out of input isn't an error when pushing data, so only
+        // compare complete data and actual errors
+        match (pushed, reader_result) {
+            (Ok((got, _)), Ok(_)) => {
+                prop_assert!(decoder.is_finished());
+                prop_assert_eq!(got, from_reader);
+            }
+            (Ok(_), Err(RefPackError::Io(error))) => {
+                prop_assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+                prop_assert!(!decoder.is_finished());
+            }
+            (Err(pushed_error), Err(reader_error)) => {
+                prop_assert_eq!(discriminant(&pushed_error), discriminant(&reader_error));
+            }
+            (pushed, reader_result) => {
+                prop_assert!(false, "{pushed:?} != {reader_result:?}");
+            }
+        }
+    }
+
+    /// Reference data from a list of controls
+    fn encode(decompressed_length: u32, controls: &[Control]) -> Vec<u8> {
+        let mut compressed = Cursor::new(vec![]);
+        Header {
+            decompressed_length,
+            ..Header::default()
+        }
+        .write::<<Reference as Format>::HeaderMode>(&mut compressed)
+        .unwrap();
+        for control in controls {
+            control.write(&mut compressed).unwrap();
+        }
+        compressed.into_inner()
+    }
+
+    #[test]
+    fn resumes_split_long_command() {
+        let long = Command::new(4, 100, 0);
+        assert_eq!(long.kind, CommandKind::Long);
+        let compressed = encode(
+            104,
+            &[
+                Control::new_literal_block(&[1, 2, 3, 4]),
+                Control::new(long, vec![]),
+                Control::new_stop(&[]),
+            ],
+        );
+
+        // header and literals, then the long command cut after 2 bytes
+        let mut decoder = Decoder::new::<Reference>();
+        let first = decoder.feed(&compressed[..11]).unwrap();
+        assert_eq!(first.consumed, 11);
+        assert_eq!(first.produced, 4);
+        assert!(!first.finished);
+
+        let second = decoder.feed(&compressed[11..]).unwrap();
+        assert_eq!(second.consumed, compressed.len() - 11);
+        assert_eq!(second.produced, 100);
+        assert!(second.finished);
+        assert_eq!(decoder.output(), [1, 2, 3, 4].repeat(26));
+    }
+
+    #[test]
+    fn resumes_split_literals() {
+        let compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+
+        let mut decoder = Decoder::new::<Reference>();
+        let mut out = vec![];
+        for byte in &compressed {
+            let progress = decoder.feed(std::slice::from_ref(byte)).unwrap();
+            assert_eq!(progress.consumed, 1);
+            out.extend_from_slice(decoder.output());
+            decoder.consume_output(progress.produced);
+        }
+
+        assert!(decoder.is_finished());
+        assert_eq!(out, b"Hello World!");
+    }
+
+    #[test]
+    fn stops_consuming_at_stopcode() {
+        let mut compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let length = compressed.len();
+        compressed.extend_from_slice(b"trailing");
+
+        let mut decoder = Decoder::new::<Reference>();
+        let progress = decoder.feed(&compressed).unwrap();
+
+        assert_eq!(progress.consumed, length);
+        assert!(progress.finished);
+        assert_eq!(decoder.feed(b"more").unwrap().consumed, 0);
+        assert_eq!(decoder.output(), b"Hello World!");
+    }
+
+    #[test]
+    fn errors_on_bad_offset() {
+        let compressed = encode(8, &[Control::new(Command::new(5, 3, 1), vec![0xAA])]);
+
+        let mut decoder = Decoder::new::<Reference>();
+        let error = decoder.feed(&compressed).unwrap_err();
+
+        assert!(matches!(
+            error,
+            RefPackError::ControlError { position: 1, .. }
+        ));
+        assert_eq!(decoder.output(), [0xAA]);
+    }
+}
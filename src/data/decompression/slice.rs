@@ -0,0 +1,130 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Decompression of input that is entirely in memory
+//!
+//! With the whole input available, controls are parsed by indexing into the
+//! slice directly and literals are copied in one go, rather than going through
+//! [Read](std::io::Read) for every byte. Output is appended to the buffer
+//! instead of being written into a zeroed buffer sized from the header.
+
+use crate::RefPackResult;
+use crate::data::decompression::decode::{SliceSink, SliceSource, decode_control};
+use crate::data::decompression::{DecompressionOptions, DecompressionReport, decode_append};
+use crate::format::Format;
+use crate::header::Header;
+
+/// Decompress `input` onto the end of `buffer`, leaving any existing contents
+/// intact. Equivalent to decompressing through a reader, including in how
+/// `options` are applied.
+///
+/// On error `buffer` is truncated back to its original length
+pub(crate) fn decompress_slice_append<F: Format>(
+    input: &[u8],
+    buffer: &mut Vec<u8>,
+    options: DecompressionOptions,
+) -> RefPackResult<DecompressionReport> {
+    let mut reader = input;
+    let header = Header::read::<F::HeaderMode>(&mut reader)?;
+    let mut source = SliceSource {
+        input,
+        position: input.len() - reader.len(),
+    };
+    decode_append(&mut source, header, buffer, options)
+}
+
+/// Decode the controls in `input` from `position` into a fixed size buffer,
+/// erroring instead of growing it
+///
+/// # Returns
+/// the number of bytes written to the start of `out`
+pub(crate) fn decode_slice_into(
+    input: &[u8],
+    position: usize,
+    out: &mut [u8],
+) -> RefPackResult<usize> {
+    let mut source = SliceSource { input, position };
+    let mut sink = SliceSink { out, written: 0 };
+
+    while !decode_control(&mut source, &mut sink)?.is_stop() {}
+
+    Ok(sink.written)
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem::discriminant;
+
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::CompressionOptions;
+    use crate::easy_compress;
+    use crate::format::Reference;
+
+    #[proptest]
+    fn matches_reader_decompression(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        #[strategy(proptest::collection::vec(any::<u8>(), 0..=100))] existing: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+
+        let mut from_slice = existing.clone();
+        let slice_report = decompress_slice_append::<Reference>(
+            &compressed,
+            &mut from_slice,
+            DecompressionOptions::default(),
+        )?;
+
+        let mut from_reader = vec![];
+        let reader_report =
+            crate::decompress::<Reference>(&mut compressed.as_slice(), &mut from_reader)?;
+
+        prop_assert_eq!(slice_report, reader_report);
+        prop_assert_eq!(&from_slice[..existing.len()], &existing[..]);
+        prop_assert_eq!(&from_slice[existing.len()..], &from_reader[..]);
+    }
+
+    #[proptest]
+    fn matches_reader_on_corrupt_data(
+        #[strategy(proptest::collection::vec(any::<u8>(), 0..=100))] data: Vec<u8>,
+    ) {
+        let from_slice = decompress_slice_append::<Reference>(
+            &data,
+            &mut vec![],
+            DecompressionOptions {
+                max_output: Some(1 << 16),
+                ..Default::default()
+            },
+        );
+
+        let mut from_reader = vec![];
+        let reader_result = crate::decompress_with_options::<Reference>(
+            &mut data.as_slice(),
+            &mut from_reader,
+            DecompressionOptions {
+                max_output: Some(1 << 16),
+                ..Default::default()
+            },
+        );
+
+        // IO errors differ in their messages, so only compare the kind of error
+        match (from_slice, reader_result) {
+            (Ok(slice_report), Ok(reader_report)) => {
+                prop_assert_eq!(slice_report, reader_report);
+            }
+            (Err(slice_error), Err(reader_error)) => {
+                prop_assert_eq!(discriminant(&slice_error), discriminant(&reader_error));
+            }
+            (from_slice, reader_result) => {
+                prop_assert!(false, "{from_slice:?} != {reader_result:?}");
+            }
+        }
+    }
+}
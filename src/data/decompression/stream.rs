@@ -0,0 +1,332 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Streaming decompression through the [Read] trait
+//!
+//! Rather than decoding the entire stream into a buffer sized from the header,
+//! the decoder only keeps the last [LONG_OFFSET_MAX] bytes of output around,
+//! since that is the furthest any copy command is able to reach back.
+
+use std::cmp::min;
+use std::io;
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::data::control::{LITERAL_MAX, LONG_LENGTH_MAX, LONG_OFFSET_MAX};
+use crate::data::decompression::decode::{ReaderSource, VecSink, decode_control};
+use crate::format::Format;
+use crate::header::Header;
+use crate::{RefPackError, RefPackResult};
+
+/// Amount of history that always has to be kept around for copy commands
+pub(super) const WINDOW_SIZE: usize = LONG_OFFSET_MAX as usize;
+
+/// Amount of already read output that is allowed to pile up past the window
+/// before it gets discarded. Discarding moves the entire window, so this keeps
+/// the cost of doing so amortized.
+pub(super) const WINDOW_SLACK: usize = 1 << 16;
+
+/// Largest amount of bytes a single command can output
+const MAX_COMMAND_OUTPUT: usize = LONG_LENGTH_MAX as usize + LITERAL_MAX as usize;
+
+/// Largest size the window can grow to: the history, the slack that is allowed
+/// to pile up before discarding, and up to the slack in unread output
+const WINDOW_CAPACITY: usize = WINDOW_SIZE + 2 * WINDOW_SLACK + MAX_COMMAND_OUTPUT;
+
+/// Decompresses `refpack` data from a reader as it is being read.
+///
+/// Peak memory usage is bounded by the maximum copy offset of refpack rather
+/// than the size of the decompressed data, which makes this suitable for very
+/// large streams. The decompressed length in the header is not used for
+/// anything other than being available through [header](Self::header).
+///
+/// Reads return `0` once the stopcode has been reached. Errors from
+/// decompression are returned as [io::Error]s wrapping the [RefPackError].
+/// The command that failed has already been consumed at that point, so the
+/// decoder can't continue: every later read returns the same error again.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{Cursor, Read};
+///
+/// use refpack::format::Reference;
+/// use refpack::{CompressionOptions, RefPackDecoder, easy_compress};
+///
+/// let compressed = easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+///
+/// let mut decoder = RefPackDecoder::new::<Reference>(Cursor::new(compressed)).unwrap();
+/// let mut out = vec![];
+/// decoder.read_to_end(&mut out).unwrap();
+///
+/// assert_eq!(out, b"Hello World!");
+/// ```
+pub struct RefPackDecoder<R: Read> {
+    source: ReaderSource<R>,
+    header: Header,
+    /// decompressed output, of which at least the last [WINDOW_SIZE] bytes
+    /// before `read_position` are kept
+    window: Vec<u8>,
+    /// position in the window of the first byte that has not been read yet
+    read_position: usize,
+    /// number of bytes that have been discarded from the front of the window
+    discarded: usize,
+    /// whether the stopcode has been decoded
+    finished: bool,
+    /// error that decoding failed with, returned again by every later read
+    failed: Option<Arc<RefPackError>>,
+}
+
+impl<R: Read> RefPackDecoder<R> {
+    /// Create a new decoder, reading the header of format `F` from the reader
+    /// immediately.
+    ///
+    /// # Errors
+    /// - [RefPackError::BadMagic]: Header magic was malformed
+    /// - [RefPackError::BadFlags]: Header flags were malformed
+    /// - [RefPackError::Io]: Generic IO error occurred while reading the header
+    pub fn new<F: Format>(mut reader: R) -> RefPackResult<Self> {
+        let header = Header::read::<F::HeaderMode>(&mut reader)?;
+
+        Ok(Self {
+            source: ReaderSource::new(reader),
+            header,
+            window: Vec::with_capacity(WINDOW_CAPACITY),
+            read_position: 0,
+            discarded: 0,
+            finished: false,
+            failed: None,
+        })
+    }
+
+    /// The header that was read when the decoder was created
+    #[must_use]
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// Total number of decompressed bytes that have been read from the decoder
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.discarded + self.read_position
+    }
+
+    /// Returns true once the stopcode has been decoded and all output has been
+    /// read
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.read_position == self.window.len()
+    }
+
+    /// Gets a reference to the underlying reader
+    #[must_use]
+    pub fn get_ref(&self) -> &R {
+        self.source.reader.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying reader
+    ///
+    /// Reading from the underlying reader will corrupt the decompressed output.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.source.reader.get_mut()
+    }
+
+    /// Unwraps the decoder, returning the underlying reader
+    ///
+    /// Once the decoder is finished, the reader is positioned directly after
+    /// the stopcode and its literal bytes.
+    pub fn into_inner(self) -> R {
+        self.source.into_inner()
+    }
+
+    /// Drops output that has already been read and is too far back to be
+    /// referenced anymore
+    fn discard_read(&mut self) {
+        if self.read_position > WINDOW_SIZE + WINDOW_SLACK {
+            let discard = self.read_position - WINDOW_SIZE;
+            self.window.copy_within(discard.., 0);
+            self.window.truncate(self.window.len() - discard);
+            self.read_position -= discard;
+            self.discarded += discard;
+        }
+    }
+
+    /// Decode a single command and its literals into the window
+    fn decode_command(&mut self) -> RefPackResult<()> {
+        let start = self.window.len();
+        let mut sink = VecSink {
+            buffer: &mut self.window,
+            start: 0,
+            discarded: self.discarded,
+        };
+
+        match decode_control(&mut self.source, &mut sink) {
+            Ok(command) => {
+                self.finished = command.is_stop();
+                Ok(())
+            }
+            Err(error) => {
+                // don't leave any partially decoded output behind
+                self.window.truncate(start);
+                Err(error)
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for RefPackDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.discard_read();
+
+        // decode enough to fill the buffer, but never more than the slack
+        // to keep memory usage bounded
+        let wanted = min(buf.len(), WINDOW_SLACK);
+        while !self.finished && self.window.len() - self.read_position < wanted {
+            if self.failed.is_none() {
+                self.failed = self.decode_command().err().map(Arc::new);
+            }
+            if let Some(error) = &self.failed {
+                let kind = match error.as_ref() {
+                    RefPackError::Io(error) => error.kind(),
+                    _ => io::ErrorKind::InvalidData,
+                };
+                return Err(io::Error::new(kind, Arc::clone(error)));
+            }
+        }
+
+        let available = &self.window[self.read_position..];
+        let amount = min(buf.len(), available.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.read_position += amount;
+
+        Ok(amount)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, ErrorKind};
+
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::CompressionOptions;
+    use crate::data::control::{Command, Control};
+    use crate::easy_compress;
+    use crate::format::Reference;
+
+    #[proptest]
+    fn symmetrical_stream_decompression(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=10_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+        #[strategy(1..=4096usize)] chunk_size: usize,
+    ) {
+        let compressed = easy_compress::<Reference>(&input, compression_options)?;
+
+        let mut decoder = RefPackDecoder::new::<Reference>(Cursor::new(compressed))?;
+        let mut got = vec![];
+        let mut chunk = vec![0; chunk_size];
+        loop {
+            let read = decoder.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            got.extend_from_slice(&chunk[..read]);
+        }
+
+        prop_assert!(decoder.is_finished());
+        prop_assert_eq!(got, input);
+    }
+
+    #[test]
+    fn decompresses_past_window() {
+        // long enough to have the window discarded several times over,
+        // with copies that reach all the way back
+        let input: Vec<u8> = (0..(WINDOW_SIZE * 6) as u32)
+            .map(|i| ((i / 7) ^ (i % (WINDOW_SIZE as u32 - 3))) as u8)
+            .collect();
+        let compressed = easy_compress::<Reference>(&input, CompressionOptions::Fast).unwrap();
+
+        let mut decoder = RefPackDecoder::new::<Reference>(Cursor::new(compressed)).unwrap();
+        let mut got = vec![];
+        decoder.read_to_end(&mut got).unwrap();
+
+        assert_eq!(decoder.position(), input.len());
+        assert_eq!(decoder.window.capacity(), WINDOW_CAPACITY);
+        assert!(got == input);
+    }
+
+    #[test]
+    fn leaves_reader_after_stopcode() {
+        let mut compressed =
+            easy_compress::<Reference>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let length = compressed.len();
+        compressed.extend_from_slice(b"trailing");
+
+        let mut decoder = RefPackDecoder::new::<Reference>(Cursor::new(compressed)).unwrap();
+        let mut got = vec![];
+        decoder.read_to_end(&mut got).unwrap();
+
+        assert_eq!(got, b"Hello World!");
+        assert_eq!(decoder.into_inner().position(), length as u64);
+    }
+
+    #[test]
+    fn errors_on_bad_offset() {
+        let mut compressed = Cursor::new(vec![]);
+        Header {
+            decompressed_length: 8,
+            ..Header::default()
+        }
+        .write::<<Reference as Format>::HeaderMode>(&mut compressed)
+        .unwrap();
+        Control::new(Command::new(5, 3, 1), vec![0])
+            .write(&mut compressed)
+            .unwrap();
+        compressed.set_position(0);
+
+        let mut decoder = RefPackDecoder::new::<Reference>(compressed).unwrap();
+        let error = decoder.read_to_end(&mut vec![]).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(decoder.position(), 0);
+    }
+
+    #[test]
+    fn keeps_failing_after_error() {
+        let mut compressed = Cursor::new(vec![]);
+        Header {
+            decompressed_length: 8,
+            ..Header::default()
+        }
+        .write::<<Reference as Format>::HeaderMode>(&mut compressed)
+        .unwrap();
+        Control::new(Command::new(5, 3, 1), vec![0])
+            .write(&mut compressed)
+            .unwrap();
+        // valid controls after the bad one must not be decoded
+        Control::new_literal_block(b"abcd")
+            .write(&mut compressed)
+            .unwrap();
+        Control::new_stop(&[]).write(&mut compressed).unwrap();
+        compressed.set_position(0);
+
+        let mut decoder = RefPackDecoder::new::<Reference>(compressed).unwrap();
+        let mut buf = [0; 16];
+        for _ in 0..3 {
+            let error = decoder.read(&mut buf).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+        assert_eq!(decoder.position(), 0);
+        assert!(!decoder.is_finished());
+    }
+}
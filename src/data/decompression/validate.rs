@@ -0,0 +1,271 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Checking compressed data for validity without decompressing it
+
+use std::fmt::{Display, Formatter};
+
+use crate::data::DecodeError;
+use crate::data::decompression::compressed_length_matches;
+use crate::data::decompression::decode::{CountSink, SliceSource, decode_control};
+use crate::format::Format;
+use crate::header::Header;
+use crate::{RefPackError, RefPackResult};
+
+/// Ways in which compressed data can be invalid, as reported by [validate]
+#[derive(Debug)]
+pub enum Violation {
+    /// A copy command would copy from outside of the output decoded so far
+    Control(DecodeError),
+    /// The input ended before a stopcode was found
+    MissingStop,
+    /// The decompressed length does not match the one in the header
+    DecompressedLength { header: u32, actual: usize },
+    /// The compressed length does not match the one in the header
+    CompressedLength { header: u32, actual: usize },
+    /// More data followed the stopcode
+    TrailingData,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::Control(error) => write!(f, "{error}"),
+            Violation::MissingStop => write!(f, "Input ended before a stopcode"),
+            Violation::DecompressedLength { header, actual } => {
+                write!(
+                    f,
+                    "Decompressed length `{actual}` does not match `{header}` in header"
+                )
+            }
+            Violation::CompressedLength { header, actual } => {
+                write!(
+                    f,
+                    "Compressed length `{actual}` does not match `{header}` in header"
+                )
+            }
+            Violation::TrailingData => write!(f, "Data continues past the stopcode"),
+        }
+    }
+}
+
+/// Statistics of compressed data that passed [validate]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct StreamSummary {
+    /// The header of the data
+    pub header: Header,
+    /// Number of bytes of input up to and including the stopcode, including the
+    /// header
+    pub compressed_length: usize,
+    /// Number of bytes the data decompresses to
+    pub decompressed_length: usize,
+    /// Number of controls in the data, including the stopcode
+    pub controls: usize,
+}
+
+/// Check that `input` is valid `refpack` data without decompressing it.
+///
+/// Controls are walked without producing any output, checking that:
+/// - every copy stays within the output produced before it
+/// - the input contains a stopcode
+/// - the decompressed length matches the one in the header
+/// - the compressed length matches the one in the header, if it has one. This
+///   can either be the length of the data following the header, or of the
+///   entire data including the header.
+///
+/// Anything after the stopcode is ignored. Since no output is produced, this
+/// never allocates regardless of the lengths in the header.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::Maxis;
+/// use refpack::{CompressionOptions, easy_compress, validate};
+///
+/// let compressed = easy_compress::<Maxis>(b"Hello World!", CompressionOptions::Fast).unwrap();
+///
+/// let summary = validate::<Maxis>(&compressed).unwrap();
+/// assert_eq!(summary.decompressed_length, 12);
+/// assert_eq!(summary.compressed_length, compressed.len());
+/// ```
+///
+/// # Errors
+/// - [RefPackError::Validation]: The first violation that was found, with the
+///   positions in the input and output at the control it was found at
+/// - [RefPackError::BadMagic]: Header magic was malformed
+/// - [RefPackError::BadFlags]: Header flags were malformed
+/// - [RefPackError::Io]: The input ended within the header
+pub fn validate<F: Format>(input: &[u8]) -> RefPackResult<StreamSummary> {
+    let mut reader = input;
+    let header = Header::read::<F::HeaderMode>(&mut reader)?;
+    let header_length = input.len() - reader.len();
+
+    let mut source = SliceSource {
+        input,
+        position: header_length,
+    };
+    let mut sink = CountSink::default();
+    let mut controls = 0usize;
+
+    loop {
+        let compressed_position = source.position;
+        let decompressed_position = sink.produced;
+        let violation = |violation| {
+            RefPackError::Validation {
+                violation,
+                compressed_position,
+                decompressed_position,
+            }
+        };
+
+        let command = decode_control(&mut source, &mut sink).map_err(|error| {
+            match error {
+                RefPackError::ControlError { error, .. } => violation(Violation::Control(error)),
+                _ => violation(Violation::MissingStop),
+            }
+        })?;
+        controls += 1;
+
+        // report overruns at the control that causes them
+        if sink.produced > header.decompressed_length as usize {
+            return Err(violation(Violation::DecompressedLength {
+                header: header.decompressed_length,
+                actual: sink.produced,
+            }));
+        }
+
+        if command.is_stop() {
+            break;
+        }
+    }
+
+    let compressed_length = source.position;
+    let decompressed_position = sink.produced;
+    let end_violation = |violation| {
+        RefPackError::Validation {
+            violation,
+            compressed_position: compressed_length,
+            decompressed_position,
+        }
+    };
+
+    if decompressed_position != header.decompressed_length as usize {
+        return Err(end_violation(Violation::DecompressedLength {
+            header: header.decompressed_length,
+            actual: decompressed_position,
+        }));
+    }
+
+    if !compressed_length_matches(&header, header_length, compressed_length) {
+        return Err(end_violation(Violation::CompressedLength {
+            header: header.compressed_length.unwrap_or(0),
+            actual: compressed_length - header_length,
+        }));
+    }
+
+    Ok(StreamSummary {
+        header,
+        compressed_length,
+        decompressed_length: decompressed_position,
+        controls,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::CompressionOptions;
+    use crate::easy_compress;
+    use crate::format::{Maxis, Reference};
+
+    fn assert_violation(
+        result: RefPackResult<StreamSummary>,
+        want_compressed: usize,
+        want_decompressed: usize,
+    ) -> Violation {
+        match result {
+            Err(RefPackError::Validation {
+                violation,
+                compressed_position,
+                decompressed_position,
+            }) => {
+                assert_eq!(compressed_position, want_compressed);
+                assert_eq!(decompressed_position, want_decompressed);
+                violation
+            }
+            other => panic!("expected a violation, got {other:?}"),
+        }
+    }
+
+    #[proptest]
+    fn validates_compressed(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = easy_compress::<Maxis>(&input, compression_options)?;
+        let summary = validate::<Maxis>(&compressed)?;
+
+        prop_assert_eq!(summary.compressed_length, compressed.len());
+        prop_assert_eq!(summary.decompressed_length, input.len());
+    }
+
+    #[test]
+    fn reports_bad_offset() {
+        // 4 byte literal, then a short copy reaching back 8 bytes
+        let data = [0x10, 0x00, 0x00, 0x00, 0xE0, 1, 2, 3, 4, 0x00, 0x07, 0xFC];
+        let violation = assert_violation(validate::<Reference>(&data), 9, 4);
+
+        assert!(matches!(
+            violation,
+            Violation::Control(DecodeError::NegativePosition(4, 8))
+        ));
+    }
+
+    #[test]
+    fn reports_missing_stop() {
+        let data = [0x04, 0x00, 0x00, 0x00, 0xE0, 1, 2, 3, 4];
+        let violation = assert_violation(validate::<Reference>(&data), 9, 4);
+
+        assert!(matches!(violation, Violation::MissingStop));
+    }
+
+    #[test]
+    fn reports_truncated_literal() {
+        let data = [0x08, 0x00, 0x00, 0x00, 0xE0, 1, 2, 3, 4, 0xFD];
+        let violation = assert_violation(validate::<Reference>(&data), 9, 4);
+
+        assert!(matches!(violation, Violation::MissingStop));
+    }
+
+    #[test]
+    fn reports_decompressed_length() {
+        let data = [0x05, 0x00, 0x00, 0x00, 0xE0, 1, 2, 3, 4, 0xFC];
+        let violation = assert_violation(validate::<Reference>(&data), 10, 4);
+
+        assert!(matches!(
+            violation,
+            Violation::DecompressedLength {
+                header: 5,
+                actual: 4
+            }
+        ));
+    }
+
+    #[test]
+    fn reports_compressed_length() {
+        let mut compressed =
+            easy_compress::<Maxis>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        compressed[0] += 1;
+        let length = compressed.len();
+        let violation = assert_violation(validate::<Maxis>(&compressed), length, 12);
+
+        assert!(matches!(violation, Violation::CompressedLength { .. }));
+    }
+}
@@ -10,10 +10,13 @@
 
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Seek};
+use std::io::Read;
 
 use crate::RefPackError;
 
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod batch;
 pub mod compression;
 pub mod control;
 pub mod decompression;
@@ -65,12 +68,34 @@ impl Display for DecodeError {
 
 impl Error for DecodeError {}
 
+/// Size of the word that short patterns are repeated into
+pub(crate) const SPLAT_WORD_SIZE: usize = 8;
+
+/// Repeat `pattern` into a word. The length of `pattern` has to evenly divide
+/// [SPLAT_WORD_SIZE] for the word to be repeatable itself.
+#[inline(always)]
+fn splat_word(pattern: &[u8]) -> [u8; SPLAT_WORD_SIZE] {
+    let mut word = [0; SPLAT_WORD_SIZE];
+    for (i, byte) in word.iter_mut().enumerate() {
+        *byte = pattern[i % pattern.len()];
+    }
+    word
+}
+
 /// Fast decoding of run length encoded data
 /// Based on https://github.com/WanzenBug/rle-decode-helper/blob/master/src/lib.rs
 ///
 /// Takes the last `offset` items of the buffer and repeatedly copies them
 /// to `position` until `length` items have been copied.
 ///
+/// The way the copy is done depends on the offset:
+/// - copies that don't overlap are a single straight copy
+/// - an offset of 1 repeats a single byte, which is a fill
+/// - offsets that evenly divide a word (2, 4 and 8) repeat the pattern into a
+///   word, which is then written over and over
+/// - anything else doubles the amount copied every step, as the copied region
+///   grows
+///
 /// If this function errors no data will have been copied
 ///
 /// # Errors
@@ -84,11 +109,11 @@ impl Error for DecodeError {}
 /// # Returns
 /// the new position of the buffer after the read
 #[inline(always)]
-pub(crate) fn rle_decode_fixed<T: Copy>(
-    buffer: &mut [T],
-    mut position: usize,
-    mut offset: usize,
-    mut length: usize,
+pub(crate) fn rle_decode_fixed(
+    buffer: &mut [u8],
+    position: usize,
+    offset: usize,
+    length: usize,
 ) -> Result<usize, DecodeError> {
     if offset == 0 {
         return Err(DecodeError::BadOffset);
@@ -102,20 +127,44 @@ pub(crate) fn rle_decode_fixed<T: Copy>(
 
     let copy_fragment_start = position - offset;
 
+    if offset >= length {
+        buffer.copy_within(
+            copy_fragment_start..(copy_fragment_start + length),
+            position,
+        );
+    } else if offset == 1 {
+        let byte = buffer[copy_fragment_start];
+        buffer[position..(position + length)].fill(byte);
+    } else if SPLAT_WORD_SIZE % offset == 0 {
+        let word = splat_word(&buffer[copy_fragment_start..position]);
+        let mut chunks = buffer[position..(position + length)].chunks_exact_mut(SPLAT_WORD_SIZE);
+        for chunk in &mut chunks {
+            chunk.copy_from_slice(&word);
+        }
+        let remainder = chunks.into_remainder();
+        let remainder_length = remainder.len();
+        remainder.copy_from_slice(&word[..remainder_length]);
+    } else {
+        copy_doubling(buffer, copy_fragment_start, position, length);
+    }
+
+    Ok(position + length)
+}
+
+/// Repeat everything from `start` up to `position` at `position` until
+/// `length` bytes have been copied, doubling the amount copied every step
+#[inline(always)]
+fn copy_doubling(buffer: &mut [u8], start: usize, mut position: usize, mut length: usize) {
+    let mut offset = position - start;
+
     while length > offset {
-        buffer.copy_within(copy_fragment_start..position, position);
+        buffer.copy_within(start..position, position);
         length -= offset;
         position += offset;
         offset *= 2;
     }
 
-    buffer.copy_within(
-        copy_fragment_start..(copy_fragment_start + length),
-        position,
-    );
-    position += length;
-
-    Ok(position)
+    buffer.copy_within(start..(start + length), position);
 }
 
 /// Copy `length` bytes from the reader into `buffer` at `position`
@@ -131,7 +180,7 @@ pub(crate) fn rle_decode_fixed<T: Copy>(
 #[inline(always)]
 pub(crate) fn copy_from_reader(
     buffer: &mut [u8],
-    reader: &mut (impl Read + Seek),
+    reader: &mut impl Read,
     position: usize,
     length: usize,
 ) -> Result<usize, RefPackError> {
@@ -147,6 +196,47 @@ pub(crate) fn copy_from_reader(
     Ok(position + length)
 }
 
+/// Reader wrapper that keeps track of the amount of bytes read through it
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: usize,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+
+    /// Number of bytes read so far
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Gets a reference to the underlying reader
+    pub(crate) fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps the reader, returning the underlying reader
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    #[inline(always)]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read;
+        Ok(read)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use proptest::prelude::*;
@@ -187,6 +277,31 @@ mod test {
 
         use super::*;
 
+        /// Byte by byte decoding, as described in the decompression module
+        fn rle_decode_naive(buffer: &mut [u8], position: usize, offset: usize, length: usize) {
+            for i in position..position + length {
+                buffer[i] = buffer[i - offset];
+            }
+        }
+
+        #[proptest]
+        fn matches_naive_decoding(
+            #[strategy(proptest::collection::vec(any::<u8>(), 1..=64))] history: Vec<u8>,
+            #[strategy(1..=#history.len())] offset: usize,
+            #[strategy(0..=300usize)] length: usize,
+        ) {
+            let position = history.len();
+            let mut want = history.clone();
+            want.resize(position + length + 8, 0);
+            let mut got = want.clone();
+
+            rle_decode_naive(&mut want, position, offset, length);
+            let end = rle_decode_fixed(&mut got, position, offset, length)?;
+
+            prop_assert_eq!(end, position + length);
+            prop_assert_eq!(got, want);
+        }
+
         #[test]
         fn errors_on_bad_offset() {
             let error = rle_decode_fixed(&mut [0], 0, 0, 1).unwrap_err();
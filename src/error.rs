@@ -8,6 +8,7 @@
 use std::fmt::{Display, Formatter};
 
 use crate::data::DecodeError;
+use crate::data::decompression::Violation;
 
 /// Possible errors returned by compression and decompression functions
 #[derive(Debug)]
@@ -31,6 +32,37 @@ pub enum Error {
     ///
     /// See [DecodeError] for further details on types of errors that can occur.
     ControlError { error: DecodeError, position: usize },
+    /// Error indicating that decompression would have produced more output than
+    /// allowed by the [DecompressionOptions](crate::DecompressionOptions) in
+    /// use.
+    ///
+    /// ### Fields
+    /// - limit: the maximum amount of output allowed at the point of failure
+    /// - length: the amount of output that would have been needed
+    LimitExceeded { limit: usize, length: usize },
+    /// Error that occurs when a length is too large to be represented by the
+    /// header [Mode](crate::header::mode::Mode) in use, either the length of
+    /// the input or of the compressed data.
+    ///
+    /// ### Fields
+    /// - max: the largest length the header can represent
+    /// - got: the length that would have had to be written
+    InputTooLarge { max: usize, got: usize },
+    /// Error returned by [validate](crate::validate) when the data is not
+    /// valid, as well as by decompression in
+    /// [strict](crate::DecompressionOptions::strict) mode.
+    ///
+    /// ### Fields
+    /// - violation: what was wrong with the data
+    /// - compressed_position: position in the input of the control at which
+    ///   the violation was found, or of the end of the data
+    /// - decompressed_position: amount of output that would have been decoded
+    ///   before that control
+    Validation {
+        violation: Violation,
+        compressed_position: usize,
+        decompressed_position: usize,
+    },
     /// Generic IO Error wrapper for when a generic IO error of some sort occurs
     /// in relation to the readers and writers.
     Io(std::io::Error),
@@ -42,6 +74,15 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<Error> for std::io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(error) => error,
+            error => Self::new(std::io::ErrorKind::InvalidData, error),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -66,6 +107,31 @@ impl Display for Error {
                     "Error occured while decoding control block at position `{position}`:\n{error}"
                 )
             }
+            Error::LimitExceeded { limit, length } => {
+                write!(
+                    f,
+                    "Decompressed data would exceed the limit of `{limit}` bytes by `{}` bytes",
+                    length - limit
+                )
+            }
+            Error::InputTooLarge { max, got } => {
+                write!(
+                    f,
+                    "Length of `{got}` bytes is larger than the header can represent, the maximum \
+                     is `{max}` bytes"
+                )
+            }
+            Error::Validation {
+                violation,
+                compressed_position,
+                decompressed_position,
+            } => {
+                write!(
+                    f,
+                    "Invalid data at compressed position `{compressed_position}`, decompressed \
+                     position `{decompressed_position}`:\n{violation}"
+                )
+            }
             Error::Io(err) => {
                 write!(f, "IO Error: {err}")
             }
@@ -0,0 +1,214 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Detection of the format used by compressed data
+//!
+//! None of the headers are self describing, so detection works by trying every
+//! format and scoring how well the data fits it. The checks are heuristics,
+//! and data that is not refpack at all may still produce candidates.
+
+use std::cmp::Reverse;
+use std::io::Cursor;
+
+use crate::data::control::{Command, LONG_LENGTH_MAX};
+use crate::format::{DynFormat, Format, Maxis, Reference, SimEA};
+use crate::header::Header;
+
+/// Score for a header whose flags and magic number were present. The
+/// [Reference] header has neither, so it never gets this.
+const MAGIC_SCORE: u32 = 3;
+/// Score for a compressed length in the header that matches the data
+const COMPRESSED_LENGTH_SCORE: u32 = 3;
+/// Score for a first control that can be decoded on its own
+const FIRST_CONTROL_SCORE: u32 = 2;
+/// Score for data that ends in a stopcode
+const STOP_SCORE: u32 = 2;
+/// Score for a decompressed length that is possible for the amount of data
+const LENGTH_SCORE: u32 = 1;
+
+/// Highest ratio of decompressed to compressed bytes, reached by a long
+/// command copying its maximum length
+const MAX_RATIO: usize = LONG_LENGTH_MAX as usize / 4 + 1;
+
+/// A format that compressed data might be in, as returned by [detect]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DetectedFormat {
+    /// The format of the candidate
+    pub format: DynFormat,
+    /// The header as parsed with the format
+    pub header: Header,
+    /// How well the data fits the format. Only meaningful relative to the
+    /// scores of other candidates for the same data.
+    pub score: u32,
+}
+
+/// Detect which format compressed data is likely to be in.
+///
+/// Every format whose header can be parsed from `data` is returned as a
+/// candidate, ordered from most to least likely. Candidates are ranked on
+/// whether the header has valid flags and a magic number, whether the lengths
+/// in the header are plausible, whether the first control decodes, and
+/// whether the data ends in a stopcode.
+///
+/// `data` should contain the entire compressed data and nothing past it.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::{DynFormat, SimEA, detect};
+/// use refpack::{CompressionOptions, easy_compress};
+///
+/// let compressed = easy_compress::<SimEA>(b"Hello World!", CompressionOptions::Fast).unwrap();
+///
+/// let candidates = detect(&compressed);
+/// assert_eq!(candidates[0].format, DynFormat::SimEA);
+/// assert_eq!(candidates[0].header.decompressed_length, 12);
+/// ```
+#[must_use]
+pub fn detect(data: &[u8]) -> Vec<DetectedFormat> {
+    let mut candidates: Vec<_> = [
+        probe::<Maxis>(DynFormat::Maxis, data),
+        probe::<SimEA>(DynFormat::SimEA, data),
+        probe::<Reference>(DynFormat::Reference, data),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    // stable, so ties keep the formats with a magic number first
+    candidates.sort_by_key(|candidate| Reverse(candidate.score));
+    candidates
+}
+
+fn probe<F: Format>(format: DynFormat, data: &[u8]) -> Option<DetectedFormat> {
+    let mut reader = Cursor::new(data);
+    let header = Header::read::<F::HeaderMode>(&mut reader).ok()?;
+    let body = &data[reader.position() as usize..];
+
+    let mut score = 0;
+    if format != DynFormat::Reference {
+        score += MAGIC_SCORE;
+    }
+    if header
+        .compressed_length
+        .is_some_and(|length| length as usize == body.len() || length as usize == data.len())
+    {
+        score += COMPRESSED_LENGTH_SCORE;
+    }
+    if first_control_decodes(body) {
+        score += FIRST_CONTROL_SCORE;
+    }
+    if ends_in_stop(body) {
+        score += STOP_SCORE;
+    }
+    if plausible_length(header.decompressed_length as usize, body.len()) {
+        score += LENGTH_SCORE;
+    }
+
+    Some(DetectedFormat {
+        format,
+        header,
+        score,
+    })
+}
+
+/// The first control has nothing before it to copy from, so any copy has to
+/// stay within its own literals
+fn first_control_decodes(body: &[u8]) -> bool {
+    let mut reader = Cursor::new(body);
+    let Ok(command) = Command::read(&mut reader) else {
+        return false;
+    };
+
+    let literal_end = reader.position() as usize + command.literal as usize;
+    let copy_valid = command
+        .offset_copy()
+        .is_none_or(|(offset, _)| offset <= command.literal as usize);
+
+    literal_end <= body.len() && copy_valid
+}
+
+/// Whether one of the last bytes is a stopcode followed by exactly as many
+/// literals as it specifies
+fn ends_in_stop(body: &[u8]) -> bool {
+    (0..=3u8).any(|literal| {
+        body.len()
+            .checked_sub(literal as usize + 1)
+            .is_some_and(|position| body[position] == 0b1111_1100 | literal)
+    })
+}
+
+/// Every command outputs at least as many bytes as it takes up, apart from the
+/// one byte literal and stop controls which cover at least 4 and 0 literals.
+/// In the other direction, no command outputs more than [MAX_RATIO] times
+/// its size.
+fn plausible_length(decompressed_length: usize, compressed_length: usize) -> bool {
+    compressed_length > 0
+        && compressed_length <= decompressed_length + decompressed_length / 4 + 1
+        && decompressed_length <= compressed_length.saturating_mul(MAX_RATIO)
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::data::compression::CompressionOptions;
+    use crate::easy_compress;
+
+    fn detects<F: Format>(format: DynFormat, input: &[u8]) -> Result<(), TestCaseError> {
+        let compressed = easy_compress::<F>(input, CompressionOptions::Fast)?;
+        let candidates = detect(&compressed);
+
+        prop_assert_eq!(candidates[0].format, format);
+        prop_assert_eq!(
+            candidates[0].header.decompressed_length as usize,
+            input.len()
+        );
+        Ok(())
+    }
+
+    #[proptest]
+    fn detects_reference(
+        #[strategy(proptest::collection::vec(any::<u8>(), 1..=1_000))] input: Vec<u8>,
+    ) {
+        detects::<Reference>(DynFormat::Reference, &input)?;
+    }
+
+    #[proptest]
+    fn detects_maxis(
+        #[strategy(proptest::collection::vec(any::<u8>(), 1..=1_000))] input: Vec<u8>,
+    ) {
+        detects::<Maxis>(DynFormat::Maxis, &input)?;
+    }
+
+    #[proptest]
+    fn detects_simea(
+        #[strategy(proptest::collection::vec(any::<u8>(), 1..=1_000))] input: Vec<u8>,
+    ) {
+        detects::<SimEA>(DynFormat::SimEA, &input)?;
+    }
+
+    #[test]
+    fn no_candidates_for_short_data() {
+        assert!(detect(&[0xFC]).is_empty());
+    }
+
+    #[test]
+    fn ranks_magic_above_reference() {
+        // valid as both a SimEA and a Reference header, but only SimEA has the
+        // magic number and a matching decompressed length
+        let data = [0x10, 0xFB, 0x00, 0x00, 0x04, 0xE0, 1, 2, 3, 4, 0xFC];
+        let candidates = detect(&data);
+
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].format, DynFormat::SimEA);
+        assert_eq!(candidates[1].format, DynFormat::Reference);
+        assert!(candidates[0].score > candidates[1].score);
+    }
+}
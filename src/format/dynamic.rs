@@ -0,0 +1,384 @@
+////////////////////////////////////////////////////////////////////////////////
+// This Source Code Form is subject to the terms of the Mozilla Public         /
+// License, v. 2.0. If a copy of the MPL was not distributed with this         /
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.                   /
+//                                                                             /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Formats selected at runtime
+//!
+//! [Format] is resolved entirely at compile time, which doesn't work when the
+//! format comes from something like a config file. [DynFormat] wraps the
+//! generic functions of each format so they can be picked at runtime instead.
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::{PoisonError, RwLock};
+
+use crate::data::compression::CompressionOptions;
+use crate::format::{Format, Maxis, Reference, SimEA};
+use crate::header::Header;
+use crate::{RefPackResult, easy_compress, easy_decompress};
+
+static REFERENCE: CustomFormat = CustomFormat::new::<Reference>("Reference");
+static MAXIS: CustomFormat = CustomFormat::new::<Maxis>("Maxis");
+static SIM_EA: CustomFormat = CustomFormat::new::<SimEA>("SimEA");
+
+/// Formats registered with [DynFormat::register]
+static REGISTRY: RwLock<Vec<&'static CustomFormat>> = RwLock::new(vec![]);
+
+/// A [Format] implementation that can be used through [DynFormat]
+///
+/// Holds the functions of the format as function pointers, so any [Format]
+/// can be turned into one.
+///
+/// # Example
+///
+/// ```
+/// use refpack::format::{CustomFormat, DynFormat, Format};
+/// use refpack::header::mode::Reference as ReferenceHeader;
+///
+/// enum MyFormat {}
+///
+/// impl Format for MyFormat {
+///     type HeaderMode = ReferenceHeader;
+/// }
+///
+/// static MY_FORMAT: CustomFormat = CustomFormat::new::<MyFormat>("MyFormat");
+///
+/// let format = DynFormat::register(&MY_FORMAT).unwrap();
+/// assert_eq!("myformat".parse::<DynFormat>().unwrap(), format);
+/// ```
+pub struct CustomFormat {
+    name: &'static str,
+    compress: fn(&[u8], CompressionOptions) -> RefPackResult<Vec<u8>>,
+    decompress: fn(&[u8]) -> RefPackResult<Vec<u8>>,
+    read_header: fn(&[u8]) -> RefPackResult<Header>,
+}
+
+impl CustomFormat {
+    /// Create a format from the functions of `F`, which can be referred to as
+    /// `name`
+    #[must_use]
+    pub const fn new<F: Format>(name: &'static str) -> Self {
+        Self {
+            name,
+            compress: easy_compress::<F>,
+            decompress: easy_decompress::<F>,
+            read_header: read_header::<F>,
+        }
+    }
+
+    /// The name of the format
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl Debug for CustomFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CustomFormat")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+// Formats are identified by their name, comparing the function pointers would
+// not be reliable
+impl PartialEq for CustomFormat {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for CustomFormat {}
+
+impl Hash for CustomFormat {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+fn read_header<F: Format>(mut input: &[u8]) -> RefPackResult<Header> {
+    Header::read::<F::HeaderMode>(&mut input)
+}
+
+/// A format chosen at runtime
+///
+/// Names are parsed case insensitively with [FromStr], and are displayed with
+/// [Display]. Other formats can be made available by name with
+/// [register](Self::register).
+///
+/// # Example
+///
+/// ```
+/// use refpack::CompressionOptions;
+/// use refpack::format::DynFormat;
+///
+/// let format: DynFormat = "simea".parse().unwrap();
+///
+/// let compressed = format
+///     .compress(b"Hello World!", CompressionOptions::Fast)
+///     .unwrap();
+/// let decompressed = format.decompress(&compressed).unwrap();
+///
+/// assert_eq!(format.to_string(), "SimEA");
+/// assert_eq!(decompressed, b"Hello World!");
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum DynFormat {
+    /// [Reference]
+    Reference,
+    /// [Maxis]
+    Maxis,
+    /// [SimEA]
+    SimEA,
+    /// Any other [Format] implementation
+    Custom(&'static CustomFormat),
+}
+
+impl DynFormat {
+    /// The formats included with the library
+    pub const BUILTIN: [DynFormat; 3] = [DynFormat::Reference, DynFormat::Maxis, DynFormat::SimEA];
+
+    fn format(self) -> &'static CustomFormat {
+        match self {
+            DynFormat::Reference => &REFERENCE,
+            DynFormat::Maxis => &MAXIS,
+            DynFormat::SimEA => &SIM_EA,
+            DynFormat::Custom(format) => format,
+        }
+    }
+
+    /// The name of the format
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        self.format().name
+    }
+
+    /// Compress `input` with the format. Equivalent to [easy_compress].
+    ///
+    /// # Errors
+    /// See [easy_compress]
+    pub fn compress(
+        self,
+        input: &[u8],
+        compression_options: CompressionOptions,
+    ) -> RefPackResult<Vec<u8>> {
+        (self.format().compress)(input, compression_options)
+    }
+
+    /// Decompress `input` with the format. Equivalent to [easy_decompress].
+    ///
+    /// # Errors
+    /// See [easy_decompress]
+    pub fn decompress(self, input: &[u8]) -> RefPackResult<Vec<u8>> {
+        (self.format().decompress)(input)
+    }
+
+    /// Read the header of the format from the start of `input`
+    ///
+    /// # Errors
+    /// See [Header::read]
+    pub fn read_header(self, input: &[u8]) -> RefPackResult<Header> {
+        (self.format().read_header)(input)
+    }
+
+    /// Make `format` available by name through [FromStr].
+    ///
+    /// # Errors
+    /// - [FormatNameError::Taken]: the name is already used by a builtin or
+    ///   previously registered format, compared case insensitively
+    pub fn register(format: &'static CustomFormat) -> Result<DynFormat, FormatNameError> {
+        let mut registry = REGISTRY.write().unwrap_or_else(PoisonError::into_inner);
+
+        if Self::find(&registry, format.name).is_some() {
+            return Err(FormatNameError::Taken(format.name));
+        }
+
+        registry.push(format);
+        Ok(DynFormat::Custom(format))
+    }
+
+    /// Remove a format added with [register](Self::register), making its
+    /// name available again. Builtin formats can't be removed.
+    ///
+    /// # Returns
+    /// the removed format, or `None` if no format was registered under
+    /// `name`, compared case insensitively
+    pub fn unregister(name: &str) -> Option<DynFormat> {
+        let mut registry = REGISTRY.write().unwrap_or_else(PoisonError::into_inner);
+
+        let index = registry
+            .iter()
+            .position(|format| format.name.eq_ignore_ascii_case(name))?;
+        Some(DynFormat::Custom(registry.remove(index)))
+    }
+
+    /// All builtin and registered formats
+    #[must_use]
+    pub fn all() -> Vec<DynFormat> {
+        let registry = REGISTRY.read().unwrap_or_else(PoisonError::into_inner);
+        Self::BUILTIN
+            .into_iter()
+            .chain(registry.iter().map(|format| DynFormat::Custom(format)))
+            .collect()
+    }
+
+    fn find(registry: &[&'static CustomFormat], name: &str) -> Option<DynFormat> {
+        Self::BUILTIN
+            .into_iter()
+            .chain(registry.iter().map(|format| DynFormat::Custom(format)))
+            .find(|format| format.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl Display for DynFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DynFormat {
+    type Err = FormatNameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let registry = REGISTRY.read().unwrap_or_else(PoisonError::into_inner);
+        Self::find(&registry, s).ok_or_else(|| FormatNameError::Unknown(s.to_string()))
+    }
+}
+
+/// Errors from looking up or registering a [DynFormat] by name
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FormatNameError {
+    /// No format exists with the name
+    Unknown(String),
+    /// A format with the name already exists
+    Taken(&'static str),
+}
+
+impl Display for FormatNameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatNameError::Unknown(name) => write!(f, "Unknown format `{name}`"),
+            FormatNameError::Taken(name) => write!(f, "Format name `{name}` is already taken"),
+        }
+    }
+}
+
+impl Error for FormatNameError {}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use test_strategy::proptest;
+
+    use super::*;
+    use crate::header::mode::Maxis as MaxisHeader;
+
+    enum TestFormat {}
+
+    impl Format for TestFormat {
+        type HeaderMode = MaxisHeader;
+    }
+
+
+    #[proptest]
+    fn symmetrical_dyn_format(
+        #[strategy(proptest::collection::vec(0..=3u8, 1..=1_000))] input: Vec<u8>,
+        #[strategy(proptest::sample::select(DynFormat::BUILTIN.to_vec()))] format: DynFormat,
+        compression_options: CompressionOptions,
+    ) {
+        let compressed = format.compress(&input, compression_options)?;
+        let header = format.read_header(&compressed)?;
+        let got = format.decompress(&compressed)?;
+
+        prop_assert_eq!(header.decompressed_length as usize, input.len());
+        prop_assert_eq!(got, input);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for format in DynFormat::BUILTIN {
+            assert_eq!(format.to_string().parse::<DynFormat>().unwrap(), format);
+            assert_eq!(
+                format
+                    .to_string()
+                    .to_uppercase()
+                    .parse::<DynFormat>()
+                    .unwrap(),
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_name() {
+        assert_eq!(
+            "nonexistent".parse::<DynFormat>(),
+            Err(FormatNameError::Unknown("nonexistent".to_string()))
+        );
+    }
+
+    #[test]
+    fn registers_custom_format() {
+        // the registry is shared between tests, so every test registers a
+        // format under its own name and removes it again
+        static FORMAT: CustomFormat = CustomFormat::new::<TestFormat>("RegisterTestFormat");
+        let format = DynFormat::register(&FORMAT).unwrap();
+
+        assert_eq!("registertestformat".parse::<DynFormat>(), Ok(format));
+        assert!(DynFormat::all().contains(&format));
+        assert_eq!(
+            DynFormat::register(&FORMAT),
+            Err(FormatNameError::Taken("RegisterTestFormat"))
+        );
+
+        let compressed = format
+            .compress(b"Hello World!", CompressionOptions::Fast)
+            .unwrap();
+        assert_eq!(
+            easy_decompress::<TestFormat>(&compressed).unwrap(),
+            b"Hello World!"
+        );
+
+        assert_eq!(DynFormat::unregister("RegisterTestFormat"), Some(format));
+    }
+
+    #[test]
+    fn unregisters_custom_format() {
+        static FORMAT: CustomFormat = CustomFormat::new::<TestFormat>("UnregisterTestFormat");
+        let format = DynFormat::register(&FORMAT).unwrap();
+
+        assert_eq!(DynFormat::unregister("unregistertestformat"), Some(format));
+        assert_eq!(DynFormat::unregister("UnregisterTestFormat"), None);
+        assert!(!DynFormat::all().contains(&format));
+        assert_eq!(
+            "UnregisterTestFormat".parse::<DynFormat>(),
+            Err(FormatNameError::Unknown("UnregisterTestFormat".to_string()))
+        );
+
+        // the name can be taken again once it's free
+        assert_eq!(DynFormat::register(&FORMAT), Ok(format));
+        assert_eq!(DynFormat::unregister("UnregisterTestFormat"), Some(format));
+    }
+
+    #[test]
+    fn does_not_unregister_builtin() {
+        assert_eq!(DynFormat::unregister("maxis"), None);
+        assert_eq!("maxis".parse::<DynFormat>(), Ok(DynFormat::Maxis));
+    }
+
+    #[test]
+    fn rejects_builtin_name() {
+        static SHADOWING: CustomFormat = CustomFormat::new::<TestFormat>("maxis");
+
+        assert_eq!(
+            DynFormat::register(&SHADOWING),
+            Err(FormatNameError::Taken("maxis"))
+        );
+    }
+}
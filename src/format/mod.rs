@@ -6,6 +6,13 @@
 ////////////////////////////////////////////////////////////////////////////////
 
 //! Possible compression formats to utilize
+
+mod detect;
+mod dynamic;
+
+pub use detect::{DetectedFormat, detect};
+pub use dynamic::{CustomFormat, DynFormat, FormatNameError};
+
 use crate::header::mode::{
     Maxis as MaxisHeader,
     Mode as HeaderMode,
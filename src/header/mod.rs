@@ -16,8 +16,8 @@ use proptest::prelude::*;
 #[cfg(test)]
 use test_strategy::Arbitrary;
 
-use crate::RefPackResult;
 use crate::header::mode::Mode;
+use crate::{RefPackError, RefPackResult};
 
 pub mod mode;
 
@@ -42,6 +42,24 @@ fn generate_compressed_length(compressed_limit: Option<u32>) -> BoxedStrategy<Op
     }
 }
 
+/// Flags stored in the flag field of the header. Only
+/// [SimEA](crate::header::mode::SimEA) stores these, other modes read them as
+/// all unset and ignore them when writing.
+///
+/// Based on <http://simswiki.info/wiki.php?title=Sims_3:DBPF/Compression#Compression_Types>
+/// and <http://wiki.niotso.org/RefPack#Header>
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub struct Flags {
+    /// The lengths in the header are stored as 4 bytes instead of 3. Always
+    /// set when writing a decompressed length that doesn't fit in 3 bytes.
+    pub big_decompressed: bool,
+    /// Meaning unknown, kept as it is
+    pub restricted: bool,
+    /// The header stores the compressed length
+    pub compressed_size_present: bool,
+}
+
 /// represents a decoded header
 #[derive(Eq, PartialEq, Debug, Default, Copy, Clone)]
 #[cfg_attr(test, derive(Arbitrary))]
@@ -51,19 +69,79 @@ pub struct Header {
     pub decompressed_length: u32,
     #[cfg_attr(test, strategy(generate_compressed_length(args.compressed_limit)))]
     pub compressed_length: Option<u32>,
+    /// Flags read from the header, or to write to it
+    #[cfg_attr(test, strategy(Just(Flags::default())))]
+    pub flags: Flags,
+    /// The flag field exactly as it was read, including bits without a known
+    /// meaning. When present, modes with more than one valid flag field write
+    /// it back instead of their usual flag field, with [flags](Header::flags)
+    /// taking precedence for the bits it describes, so a header that was read
+    /// is written byte for byte the same. Bits the mode can't write are
+    /// dropped. Modes without a flag field always read it as `None`.
+    #[cfg_attr(test, strategy(Just(None)))]
+    pub raw_flags: Option<u8>,
 }
 
 impl Header {
+    /// Header describing `decompressed_length` bytes compressed into
+    /// `compressed_length` bytes, checked against the limits of the mode `M`
+    ///
+    /// # Errors
+    /// - [RefPackError::InputTooLarge]: Either length is larger than `M` can
+    ///   represent
+    pub fn with_lengths<M: Mode>(
+        decompressed_length: usize,
+        compressed_length: usize,
+    ) -> RefPackResult<Header> {
+        check_length(M::MAX_DECOMPRESSED_LENGTH, decompressed_length)?;
+        check_length(M::MAX_COMPRESSED_LENGTH, compressed_length)?;
+
+        Ok(Header {
+            decompressed_length: decompressed_length as u32,
+            // only exceeds a u32 for modes that don't store it
+            compressed_length: u32::try_from(compressed_length).ok(),
+            flags: Flags::default(),
+            raw_flags: None,
+        })
+    }
+
+    /// This header without anything that was kept from reading it, so it is
+    /// written the same way as a header of newly compressed data: unset
+    /// [flags](Header::flags) and the default flag field of the mode.
+    #[must_use]
+    pub fn canonical(self) -> Header {
+        Header {
+            flags: Flags::default(),
+            raw_flags: None,
+            ..self
+        }
+    }
+
     /// # Errors
     /// - [RefPackError::BadMagic]: Invalid magic number read
     /// - [RefPackError::Io]: Generic IO Error
-    pub fn read<M: Mode>(reader: &mut (impl Read + Seek)) -> RefPackResult<Header> {
+    pub fn read<M: Mode>(reader: &mut impl Read) -> RefPackResult<Header> {
         M::read(reader)
     }
 
     /// # Errors
+    /// - [RefPackError::InputTooLarge]: A length can't be represented by `M`
+    /// - [RefPackError::BadFlags]: [flags](Header::flags) announce a compressed
+    ///   length, but there is none to write
     /// - [RefPackError::Io] if the write fails due to a generic IO Error
     pub fn write<M: Mode>(self, writer: &mut (impl Write + Seek)) -> RefPackResult<()> {
         M::write(self, writer)
     }
 }
+
+/// Check that `length` is at most `max`
+///
+/// # Errors
+/// - [RefPackError::InputTooLarge]: `length` is larger than `max`
+pub(crate) fn check_length(max: usize, length: usize) -> RefPackResult<()> {
+    if length > max {
+        Err(RefPackError::InputTooLarge { max, got: length })
+    } else {
+        Ok(())
+    }
+}
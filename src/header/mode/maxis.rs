@@ -5,13 +5,12 @@
 //                                                                             /
 ////////////////////////////////////////////////////////////////////////////////
 
-use std::cmp::min;
 use std::io::{Read, Seek, Write};
 
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::header::Header;
 use crate::header::mode::Mode;
+use crate::header::{Flags, Header};
 use crate::{RefPackError, RefPackResult, header};
 
 /// Header used by many Maxis and SimEA games
@@ -26,11 +25,13 @@ pub enum Maxis {}
 pub const FLAGS: u8 = 0x10;
 
 impl Mode for Maxis {
+    const MAX_DECOMPRESSED_LENGTH: usize = 0xFF_FF_FF;
+
     fn length(_decompressed_size: usize) -> usize {
         9
     }
 
-    fn read<R: Read + Seek>(reader: &mut R) -> RefPackResult<Header> {
+    fn read<R: Read>(reader: &mut R) -> RefPackResult<Header> {
         let compressed_length_prewrap = reader.read_u32::<LittleEndian>()?;
         let compressed_length = if compressed_length_prewrap == 0 {
             None
@@ -50,19 +51,23 @@ impl Mode for Maxis {
         Ok(Header {
             decompressed_length,
             compressed_length,
+            flags: Flags::default(),
+            raw_flags: Some(flags),
         })
     }
 
     fn write<W: Write + Seek>(header: Header, writer: &mut W) -> RefPackResult<()> {
+        header::check_length(
+            Self::MAX_DECOMPRESSED_LENGTH,
+            header.decompressed_length as usize,
+        )?;
+
         writer.write_u32::<LittleEndian>(header.compressed_length.unwrap_or(0))?;
+        // the flag field is only ever read as `FLAGS`, so the raw flags of headers read by other
+        // modes are not carried over
         writer.write_u8(FLAGS)?;
         writer.write_u8(header::MAGIC)?;
-        // This is only ever used to create a default size for the decompression buffer,
-        // so I believe this won't cause issues? Even official decompression seems to just ignore this
-        writer.write_u24::<BigEndian>(min(
-            header.decompressed_length,
-            0b1111_1111_1111_1111_1111_1111,
-        ))?;
+        writer.write_u24::<BigEndian>(header.decompressed_length)?;
         Ok(())
     }
 }
@@ -76,6 +81,7 @@ mod test {
 
     use super::*;
     use crate::header::Header;
+    use crate::header::mode::SimEA;
 
     #[proptest]
     fn symmetrical_read_write(
@@ -87,7 +93,11 @@ mod test {
         let mut read_cur = Cursor::new(&mut write_buf);
         let got = Header::read::<Maxis>(&mut read_cur).unwrap();
 
-        prop_assert_eq!(header, got);
+        let expected = Header {
+            raw_flags: Some(FLAGS),
+            ..header
+        };
+        prop_assert_eq!(expected, got);
     }
 
     #[test]
@@ -98,6 +108,8 @@ mod test {
         let want = Header {
             decompressed_length: 255,
             compressed_length: Some(255),
+            flags: Flags::default(),
+            raw_flags: Some(FLAGS),
         };
         assert_eq!(got, want);
     }
@@ -107,6 +119,8 @@ mod test {
         let header = Header {
             decompressed_length: 255,
             compressed_length: Some(255),
+            flags: Flags::default(),
+            raw_flags: None,
         };
         let mut buf = vec![];
         let mut cur = Cursor::new(&mut buf);
@@ -115,6 +129,29 @@ mod test {
         assert_eq!(buf, want);
     }
 
+    #[test]
+    fn rewrites_sim_ea_header() {
+        // the compressed size flag, with both small and big lengths
+        for bytes in [
+            vec![0x11, 0xFB, 0, 0, 0x20, 0, 0x01, 0],
+            vec![0x91, 0xFB, 0, 0, 0, 0x20, 0, 0, 0x01, 0],
+        ] {
+            let header = Header::read::<SimEA>(&mut Cursor::new(&bytes)).unwrap();
+            let mut buf = vec![];
+            header.write::<Maxis>(&mut Cursor::new(&mut buf)).unwrap();
+            assert_eq!(buf, vec![0x20, 0, 0, 0, FLAGS, header::MAGIC, 0, 0x01, 0]);
+
+            let got = Header::read::<Maxis>(&mut Cursor::new(&buf)).unwrap();
+            let want = Header {
+                decompressed_length: 0x1_00,
+                compressed_length: Some(0x20),
+                flags: Flags::default(),
+                raw_flags: Some(FLAGS),
+            };
+            assert_eq!(got, want);
+        }
+    }
+
     #[test]
     fn rejects_bad_flags() {
         let mut buf = vec![0, 0, 0, 0, 0x50, 0, 0, 0, 0];
@@ -130,4 +167,25 @@ mod test {
         let err = Header::read::<Maxis>(&mut cur).unwrap_err();
         assert_eq!(err.to_string(), RefPackError::BadMagic(0x50).to_string());
     }
+
+    #[test]
+    fn rejects_large_decompressed_length() {
+        let header = Header {
+            decompressed_length: 0x1_00_00_00,
+            compressed_length: Some(255),
+            flags: Flags::default(),
+            raw_flags: None,
+        };
+        let mut buf = vec![];
+        let mut cur = Cursor::new(&mut buf);
+        let err = header.write::<Maxis>(&mut cur).unwrap_err();
+        assert!(matches!(
+            err,
+            RefPackError::InputTooLarge {
+                max: 0xFF_FF_FF,
+                got: 0x1_00_00_00
+            }
+        ));
+        assert!(buf.is_empty());
+    }
 }
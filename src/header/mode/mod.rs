@@ -28,23 +28,36 @@ use crate::header::Header;
 /// To implement your own commands, implement `Mode` on to a unit struct or
 /// unconstructable struct with one private member and no new method. `read` and
 /// `write` should be symmetrical, and a value fed in to read and then back out
-/// of write should yield the same result.
+/// of write should yield the same result. Anything about the header that isn't
+/// described by the other fields of [Header] should be kept in
+/// [raw_flags](Header::raw_flags), so that writing a header that was read
+/// reproduces it byte for byte.
 pub trait Mode {
+    /// Largest decompressed length the header is able to represent
+    const MAX_DECOMPRESSED_LENGTH: usize = u32::MAX as usize;
+
+    /// Largest compressed length the header is able to represent, not
+    /// counting the header itself. Modes that don't store the compressed
+    /// length have no limit.
+    const MAX_COMPRESSED_LENGTH: usize = u32::MAX as usize;
+
     /// Length of the header, used by some parsing
     fn length(decompressed_size: usize) -> usize;
 
-    /// Reads from a `Read + Seek` reader and attempts to parse a header at the
+    /// Reads from a `Read` reader and attempts to parse a header at the
     /// current position.
     ///
     /// # Errors
     /// - [RefPackError::BadMagic]: Magic number failed verification
     /// - [RefPackError::Io]: Generic IO Error occurred during read
-    fn read<R: Read + Seek>(reader: &mut R) -> RefPackResult<Header>;
+    fn read<R: Read>(reader: &mut R) -> RefPackResult<Header>;
 
     /// Writes to a `Write + Seek` writer and attempts to encode a header at the
     /// current position.
     ///
     /// # Errors
+    /// - [RefPackError::InputTooLarge]: A length of the header can't be
+    ///   represented
     /// - [RefPackError::Io]: Generic IO Error occurred during write
     fn write<W: Write + Seek>(header: Header, writer: &mut W) -> RefPackResult<()>;
 }
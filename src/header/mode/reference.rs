@@ -10,8 +10,8 @@ use std::io::{Read, Seek, Write};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::RefPackResult;
-use crate::header::Header;
 use crate::header::mode::Mode;
+use crate::header::{Flags, Header};
 
 /// Earliest "Reference" implementation of header
 ///
@@ -22,15 +22,20 @@ use crate::header::mode::Mode;
 pub enum Reference {}
 
 impl Mode for Reference {
+    // the compressed length is not stored
+    const MAX_COMPRESSED_LENGTH: usize = usize::MAX;
+
     fn length(_decompressed_size: usize) -> usize {
         4
     }
 
-    fn read<R: Read + Seek>(reader: &mut R) -> RefPackResult<Header> {
+    fn read<R: Read>(reader: &mut R) -> RefPackResult<Header> {
         let decompressed_length = reader.read_u32::<LittleEndian>()?;
         Ok(Header {
             decompressed_length,
             compressed_length: None,
+            flags: Flags::default(),
+            raw_flags: None,
         })
     }
 
@@ -55,6 +60,8 @@ mod test {
         let expected = Header {
             decompressed_length: header.decompressed_length,
             compressed_length: None,
+            flags: Flags::default(),
+            raw_flags: None,
         };
 
         let mut write_buf = vec![];
@@ -79,6 +86,8 @@ mod test {
         let header = Header {
             decompressed_length: 255,
             compressed_length: None,
+            flags: Flags::default(),
+            raw_flags: None,
         };
         let mut buf = vec![];
         let mut cur = Cursor::new(&mut buf);
@@ -9,8 +9,8 @@ use std::io::{Read, Seek, Write};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::header::Header;
 use crate::header::mode::Mode;
+use crate::header::{Flags, Header};
 use crate::{RefPackError, RefPackResult, header};
 
 /// Header used by many Maxis and SimEA games
@@ -18,83 +18,124 @@ use crate::{RefPackError, RefPackResult, header};
 /// length u32, and the use of the flags field
 ///
 /// ## Structure
-/// - u8: Flags field
+/// - u8: Flags field, see [Flags]
 /// - Magic Number: 0xFB
+/// - Big Endian u24/u32: Compressed Length, only present when
+///   [compressed_size_present](Flags::compressed_size_present) is set
 /// - Big Endian u24/u32: Decompressed Length
+///
+/// Both lengths are u32 when [big_decompressed](Flags::big_decompressed) is
+/// set, and u24 otherwise.
 pub enum SimEA {}
 
-/// The header flags
-/// Based on http://simswiki.info/wiki.php?title=Sims_3:DBPF/Compression#Compression_Types
-/// and http://wiki.niotso.org/RefPack#Header
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
-struct Flags {
-    big_decompressed: bool,
-    restricted: bool,
-    compressed_size_present: bool,
-}
+/// Bits of the flags field that have a known meaning, along with the magic
+/// number in the flags field
+const KNOWN_FLAGS: u8 = 0b1101_0001;
 
-impl Flags {
-    fn read(data: u8) -> RefPackResult<Self> {
-        if (data & 0b0010_1110) > 0 {
-            Err(RefPackError::BadFlags(data))
-        } else {
-            Ok(Self {
-                big_decompressed: (data & 0b1000_0000) > 0,
-                restricted: (data & 0b0100_0000) > 0,
-                compressed_size_present: (data & 0b0000_0001) > 0,
-            })
-        }
-    }
+/// Bits of the flags field that are described by [Flags]
+const FLAG_BITS: u8 = 0b1100_0001;
 
-    fn write(self) -> u8 {
-        ((self.big_decompressed as u8) << 7) | ((self.restricted as u8) << 6)
-            | (self.compressed_size_present as u8)
-            // magic number in the flags field, unsure if this is verified by any implementation
-            // mentioned on the niotso wiki.
-            // specifically consists of the bits within |: 0b00|01_000|0
-            | 0b0001_0000
+fn read_flags(data: u8) -> RefPackResult<Flags> {
+    if (data & !KNOWN_FLAGS) > 0 {
+        Err(RefPackError::BadFlags(data))
+    } else {
+        Ok(Flags {
+            big_decompressed: (data & 0b1000_0000) > 0,
+            restricted: (data & 0b0100_0000) > 0,
+            compressed_size_present: (data & 0b0000_0001) > 0,
+        })
     }
 }
 
+/// Encode `flags`, keeping the magic number of `raw_flags` if there are any.
+/// Bits of `raw_flags` that can't be set in a SimEA header are dropped, so the
+/// written header can always be read again.
+fn write_flags(flags: Flags, raw_flags: Option<u8>) -> u8 {
+    // magic number in the flags field, unsure if this is verified by any implementation
+    // mentioned on the niotso wiki.
+    // specifically consists of the bits within |: 0b00|01_000|0
+    let unknown = raw_flags.map_or(0b0001_0000, |raw_flags| {
+        raw_flags & KNOWN_FLAGS & !FLAG_BITS
+    });
+    ((flags.big_decompressed as u8) << 7)
+        | ((flags.restricted as u8) << 6)
+        | (flags.compressed_size_present as u8)
+        | unknown
+}
+
 impl Mode for SimEA {
+    // the compressed length is only stored when requested through the flags
+    const MAX_COMPRESSED_LENGTH: usize = usize::MAX;
+
     fn length(decompressed_size: usize) -> usize {
         if decompressed_size > 0xFF_FF_FF { 6 } else { 5 }
     }
 
-    fn read<R: Read + Seek>(reader: &mut R) -> RefPackResult<Header> {
-        let flags = Flags::read(reader.read_u8()?)?;
+    fn read<R: Read>(reader: &mut R) -> RefPackResult<Header> {
+        let raw_flags = reader.read_u8()?;
+        let flags = read_flags(raw_flags)?;
         let magic = reader.read_u8()?;
         if magic != header::MAGIC {
             return Err(RefPackError::BadMagic(magic));
         }
-        // Inexplicably this weird three byte number is stored Big Endian
-        let decompressed_length = if flags.big_decompressed {
-            reader.read_u32::<BigEndian>()?
+        // Inexplicably these weird three byte numbers are stored Big Endian
+        let mut read_length = || {
+            if flags.big_decompressed {
+                reader.read_u32::<BigEndian>()
+            } else {
+                reader.read_u24::<BigEndian>()
+            }
+        };
+        let compressed_length = if flags.compressed_size_present {
+            Some(read_length()?)
         } else {
-            reader.read_u24::<BigEndian>()?
+            None
         };
+        let decompressed_length = read_length()?;
         Ok(Header {
             decompressed_length,
-            compressed_length: None,
+            compressed_length,
+            flags,
+            raw_flags: Some(raw_flags),
         })
     }
 
     fn write<W: Write + Seek>(header: Header, writer: &mut W) -> RefPackResult<()> {
-        let big_decompressed = header.decompressed_length > 0xFF_FF_FF;
-        writer.write_u8(
-            Flags {
-                big_decompressed,
-                restricted: false,
-                compressed_size_present: false,
+        let flags = Flags {
+            big_decompressed: header.flags.big_decompressed
+                || header.decompressed_length > 0xFF_FF_FF,
+            ..header.flags
+        };
+        let max_length = if flags.big_decompressed {
+            u32::MAX
+        } else {
+            0xFF_FF_FF
+        };
+        let compressed_length = match (flags.compressed_size_present, header.compressed_length) {
+            (false, _) => None,
+            (true, Some(length)) => {
+                header::check_length(max_length as usize, length as usize)?;
+                Some(length)
             }
-            .write(),
-        )?;
+            // there is no length to put in the field the flag announces
+            (true, None) => {
+                return Err(RefPackError::BadFlags(write_flags(flags, header.raw_flags)));
+            }
+        };
+
+        writer.write_u8(write_flags(flags, header.raw_flags))?;
         writer.write_u8(header::MAGIC)?;
-        if big_decompressed {
-            writer.write_u32::<BigEndian>(header.decompressed_length)?;
-        } else {
-            writer.write_u24::<BigEndian>(header.decompressed_length)?;
+        let mut write_length = |length| {
+            if flags.big_decompressed {
+                writer.write_u32::<BigEndian>(length)
+            } else {
+                writer.write_u24::<BigEndian>(length)
+            }
+        };
+        if let Some(compressed_length) = compressed_length {
+            write_length(compressed_length)?;
         }
+        write_length(header.decompressed_length)?;
         Ok(())
     }
 }
@@ -107,7 +148,9 @@ mod test {
     use test_strategy::proptest;
 
     use super::*;
+    use crate::format::SimEA as SimEAFormat;
     use crate::header::Header;
+    use crate::{CompressionOptions, easy_compress, easy_decompress, validate};
 
     #[proptest]
     fn symmetrical_read_write(
@@ -125,7 +168,43 @@ mod test {
         let mut read_cur = Cursor::new(&mut write_buf);
         let got = Header::read::<SimEA>(&mut read_cur).unwrap();
 
-        prop_assert_eq!(header, got);
+        let expected = Header {
+            flags: Flags {
+                big_decompressed: header.decompressed_length > 0xFF_FF_FF,
+                ..header.flags
+            },
+            raw_flags: Some(write_buf[0]),
+            ..header
+        };
+        prop_assert_eq!(expected, got);
+    }
+
+    #[proptest]
+    fn symmetrical_read_write_with_flags(
+        flags: Flags,
+        #[strategy(0..=if #flags.big_decompressed { u32::MAX } else { 0xFF_FF_FF })]
+        decompressed_length: u32,
+        #[strategy(0..=if #flags.big_decompressed { u32::MAX } else { 0xFF_FF_FF })]
+        compressed_length: u32,
+    ) {
+        let header = Header {
+            decompressed_length,
+            compressed_length: flags.compressed_size_present.then_some(compressed_length),
+            flags,
+            raw_flags: None,
+        };
+
+        let mut write_buf = vec![];
+        let mut write_cur = Cursor::new(&mut write_buf);
+        header.write::<SimEA>(&mut write_cur).unwrap();
+        let mut read_cur = Cursor::new(&mut write_buf);
+        let got = Header::read::<SimEA>(&mut read_cur).unwrap();
+
+        let expected = Header {
+            raw_flags: Some(write_buf[0]),
+            ..header
+        };
+        prop_assert_eq!(expected, got);
     }
 
     #[test]
@@ -138,8 +217,8 @@ mod test {
                         restricted: *restricted,
                         compressed_size_present: *compressed_size_present,
                     };
-                    let written = flags.write();
-                    let read = Flags::read(written).unwrap();
+                    let written = write_flags(flags, None);
+                    let read = read_flags(written).unwrap();
                     assert_eq!(flags, read);
                 }
             }
@@ -150,7 +229,7 @@ mod test {
     fn flags_reads_correctly() {
         let mut buf = vec![0b0101_0000];
         let mut cur = Cursor::new(&mut buf);
-        let got = Flags::read(cur.read_u8().unwrap()).unwrap();
+        let got = read_flags(cur.read_u8().unwrap()).unwrap();
         let expected = Flags {
             big_decompressed: false,
             restricted: true,
@@ -168,7 +247,7 @@ mod test {
         };
         let mut buf = vec![];
         let mut cur = Cursor::new(&mut buf);
-        cur.write_u8(flags.write()).unwrap();
+        cur.write_u8(write_flags(flags, None)).unwrap();
         let expected = vec![0b0101_0000];
         assert_eq!(buf, expected);
     }
@@ -181,6 +260,8 @@ mod test {
         let expected = Header {
             decompressed_length: 0x12_34_56,
             compressed_length: None,
+            flags: Flags::default(),
+            raw_flags: Some(0x10),
         };
         assert_eq!(got, expected);
     }
@@ -190,6 +271,8 @@ mod test {
         let header = Header {
             decompressed_length: 0x12_34_56,
             compressed_length: None,
+            flags: Flags::default(),
+            raw_flags: None,
         };
         let mut buf = vec![];
         let mut cur = Cursor::new(&mut buf);
@@ -198,6 +281,210 @@ mod test {
         assert_eq!(buf, expected);
     }
 
+    /// Every combination of flags along with its byte layout
+    fn layouts() -> Vec<(Header, Vec<u8>)> {
+        let mut layouts = vec![];
+        for big_decompressed in [true, false] {
+            for restricted in [true, false] {
+                for compressed_size_present in [true, false] {
+                    let flags = Flags {
+                        big_decompressed,
+                        restricted,
+                        compressed_size_present,
+                    };
+                    let mut bytes = vec![write_flags(flags, None), 0xFB];
+                    match (big_decompressed, compressed_size_present) {
+                        (true, true) => bytes.extend([0, 0xAB, 0xCD, 0xEF, 0, 0x12, 0x34, 0x56]),
+                        (true, false) => bytes.extend([0, 0x12, 0x34, 0x56]),
+                        (false, true) => bytes.extend([0xAB, 0xCD, 0xEF, 0x12, 0x34, 0x56]),
+                        (false, false) => bytes.extend([0x12, 0x34, 0x56]),
+                    }
+                    let header = Header {
+                        decompressed_length: 0x12_34_56,
+                        compressed_length: compressed_size_present.then_some(0xAB_CD_EF),
+                        flags,
+                        raw_flags: None,
+                    };
+                    layouts.push((header, bytes));
+                }
+            }
+        }
+        layouts
+    }
+
+    #[test]
+    fn reads_every_flag_combo() {
+        for (expected, mut bytes) in layouts() {
+            // data following the header must not be read
+            let header_length = bytes.len();
+            bytes.extend([0xFC, 0xFC]);
+            let mut cur = Cursor::new(&bytes);
+            let got = Header::read::<SimEA>(&mut cur).unwrap();
+            let expected = Header {
+                raw_flags: Some(bytes[0]),
+                ..expected
+            };
+            assert_eq!(got, expected);
+            assert_eq!(cur.position() as usize, header_length);
+        }
+    }
+
+    #[test]
+    fn writes_every_flag_combo() {
+        for (header, expected) in layouts() {
+            let mut buf = vec![];
+            let mut cur = Cursor::new(&mut buf);
+            header.write::<SimEA>(&mut cur).unwrap();
+            assert_eq!(buf, expected);
+        }
+    }
+
+    #[test]
+    fn reads_compressed_size_layouts() {
+        let mut buf = vec![0x11, 0xFB, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00];
+        let mut cur = Cursor::new(&mut buf);
+        let got = Header::read::<SimEA>(&mut cur).unwrap();
+        assert_eq!(got.compressed_length, Some(0x20));
+        assert_eq!(got.decompressed_length, 0x1_00);
+
+        let mut buf = vec![0x91, 0xFB, 0x00, 0x00, 0x00, 0x20, 0x01, 0x00, 0x00, 0x00];
+        let mut cur = Cursor::new(&mut buf);
+        let got = Header::read::<SimEA>(&mut cur).unwrap();
+        assert_eq!(got.compressed_length, Some(0x20));
+        assert_eq!(got.decompressed_length, 0x1_00_00_00);
+    }
+
+    #[test]
+    fn decompresses_with_compressed_size() {
+        let compressed =
+            easy_compress::<SimEAFormat>(b"Hello World!", CompressionOptions::Fast).unwrap();
+        let body = &compressed[SimEA::length(12)..];
+
+        let header = Header {
+            decompressed_length: 12,
+            compressed_length: Some(body.len() as u32),
+            flags: Flags {
+                compressed_size_present: true,
+                ..Flags::default()
+            },
+            raw_flags: None,
+        };
+        let mut with_size = Cursor::new(vec![]);
+        header.write::<SimEA>(&mut with_size).unwrap();
+        let mut with_size = with_size.into_inner();
+        assert_eq!(&with_size[..2], &[0x11, 0xFB]);
+        with_size.extend_from_slice(body);
+
+        assert_eq!(
+            validate::<SimEAFormat>(&with_size)
+                .unwrap()
+                .compressed_length,
+            with_size.len()
+        );
+        assert_eq!(
+            easy_decompress::<SimEAFormat>(&with_size).unwrap(),
+            b"Hello World!"
+        );
+    }
+
+    #[test]
+    fn rewrites_read_header_exactly() {
+        // the magic bit of the flag field is not always set
+        for flags in [0x00, 0x01, 0x40, 0x10, 0x51, 0x80, 0x90, 0xD1] {
+            let mut bytes = vec![flags, 0xFB];
+            if flags & 0x01 > 0 {
+                bytes.extend([0, 0, 0x20]);
+            }
+            bytes.extend([0, 0x01, 0]);
+            if flags & 0x80 > 0 {
+                bytes.insert(2, 0);
+                if flags & 0x01 > 0 {
+                    bytes.insert(6, 0);
+                }
+            }
+
+            let header = Header::read::<SimEA>(&mut Cursor::new(&bytes)).unwrap();
+            let mut buf = vec![];
+            header.write::<SimEA>(&mut Cursor::new(&mut buf)).unwrap();
+            assert_eq!(buf, bytes, "flags {flags:#04X}");
+        }
+    }
+
+    #[test]
+    fn raw_flags_keep_magic_bit_only() {
+        for (raw_flags, expected) in [(0x01, 0x40), (0x10, 0x50), (0xFF, 0x50), (0x2E, 0x40)] {
+            let header = Header {
+                decompressed_length: 0x12_34_56,
+                compressed_length: None,
+                flags: Flags {
+                    restricted: true,
+                    ..Flags::default()
+                },
+                raw_flags: Some(raw_flags),
+            };
+            let mut buf = vec![];
+            header.write::<SimEA>(&mut Cursor::new(&mut buf)).unwrap();
+            // the flags are written as they are set in `flags`, illegal bits are dropped
+            assert_eq!(buf, vec![expected, 0xFB, 0x12, 0x34, 0x56]);
+            Header::read::<SimEA>(&mut Cursor::new(&buf)).unwrap();
+        }
+    }
+
+    #[test]
+    fn canonical_writes_default_flags() {
+        let bytes = vec![0xC1, 0xFB, 0, 0, 0, 0x20, 0, 0, 0x01, 0];
+        let header = Header::read::<SimEA>(&mut Cursor::new(&bytes)).unwrap();
+
+        let mut buf = vec![];
+        header
+            .canonical()
+            .write::<SimEA>(&mut Cursor::new(&mut buf))
+            .unwrap();
+        assert_eq!(buf, vec![0x10, 0xFB, 0, 0x01, 0]);
+    }
+
+    #[test]
+    fn rejects_large_compressed_length() {
+        let header = Header {
+            decompressed_length: 0x12_34_56,
+            compressed_length: Some(0x1_00_00_00),
+            flags: Flags {
+                compressed_size_present: true,
+                ..Flags::default()
+            },
+            raw_flags: None,
+        };
+        let mut buf = vec![];
+        let mut cur = Cursor::new(&mut buf);
+        let err = header.write::<SimEA>(&mut cur).unwrap_err();
+        assert!(matches!(
+            err,
+            RefPackError::InputTooLarge {
+                max: 0xFF_FF_FF,
+                got: 0x1_00_00_00
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_compressed_size_flag_without_length() {
+        let header = Header {
+            decompressed_length: 0x12_34_56,
+            compressed_length: None,
+            flags: Flags {
+                compressed_size_present: true,
+                ..Flags::default()
+            },
+            raw_flags: None,
+        };
+        let mut buf = vec![];
+        let err = header
+            .write::<SimEA>(&mut Cursor::new(&mut buf))
+            .unwrap_err();
+        assert_eq!(err.to_string(), RefPackError::BadFlags(0x11).to_string());
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn rejects_bad_flags() {
         let mut buf = vec![0xFF, 0];
@@ -159,8 +159,38 @@ mod error;
 pub mod format;
 pub mod header;
 
-pub use crate::data::compression::{CompressionOptions, compress, easy_compress};
-pub use crate::data::decompression::{decompress, easy_decompress};
+#[cfg(feature = "async")]
+pub use crate::data::async_io::{compress_async, decompress_async};
+pub use crate::data::batch::{compress_batch, decompress_batch};
+pub use crate::data::compression::{
+    CompressionOptions,
+    CompressionParams,
+    RefPackEncoder,
+    SegmentedReport,
+    append_compress,
+    compress,
+    compress_segmented,
+    easy_compress,
+};
+pub use crate::data::decompression::{
+    Decoder,
+    DecompressionOptions,
+    DecompressionOutcome,
+    DecompressionReport,
+    RefPackDecoder,
+    StreamSummary,
+    decompress,
+    decompress_buffered,
+    decompress_buffered_with_options,
+    decompress_into,
+    decompress_into_vec,
+    decompress_lossy,
+    decompress_prefix,
+    decompress_with_options,
+    easy_decompress,
+    easy_decompress_with_options,
+    validate,
+};
 pub use crate::error::{Error as RefPackError, Result as RefPackResult};
 
 #[cfg(test)]
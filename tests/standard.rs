@@ -1,6 +1,6 @@
 use std::io::Read;
 
-use CompressionOptions::{Fast, Fastest, Optimal};
+use CompressionOptions::{Fast, Fastest, Lazy, Optimal};
 use paste::paste;
 use refpack::format::{Format, Maxis, Reference, SimEA};
 use refpack::{CompressionOptions, easy_compress, easy_decompress};
@@ -92,6 +92,7 @@ macro_rules! corpus_test_no_mode {
     ($name:expr, $format:ident) => {
         corpus_test_final!($name, $format, Fastest);
         corpus_test_final!($name, $format, Fast);
+        corpus_test_final!($name, $format, Lazy);
         corpus_test_final!($name, $format, Optimal);
     };
 }